@@ -1,4 +1,4 @@
-use rollkit::parsing::{BinaryOperator, Expr, ExprVisitor, Literal};
+use rollkit::parsing::{BinaryOperator, DiceModifier, Expr, ExprVisitor, Literal, UnaryOperator};
 use yansi::Paint;
 
 /// Visitor that explains the structure of an expression
@@ -69,6 +69,18 @@ impl ExprVisitor for ExplainVisitor {
         }
     }
 
+    fn visit_unary_op(&mut self, op: &UnaryOperator, expr: &Expr) -> Self::Output {
+        let header = format!(
+            "{}Unary Operation: {} ({})",
+            self.indent(),
+            op.to_str().magenta(),
+            op.desc().blue()
+        );
+        let expr_str = self.with_depth(|v| v.visit_expr(expr));
+
+        format!("{}\n{}", header, expr_str)
+    }
+
     fn visit_binary_op(&mut self, left: &Expr, op: &BinaryOperator, right: &Expr) -> Self::Output {
         let header = format!(
             "{}Binary Operation: {} ({})",
@@ -82,6 +94,42 @@ impl ExprVisitor for ExplainVisitor {
         format!("{}\n{}\n{}", header, left_str, right_str)
     }
 
+    fn visit_modified_dice(
+        &mut self,
+        count: &Expr,
+        sides: &Expr,
+        modifiers: &[DiceModifier],
+    ) -> Self::Output {
+        let op = BinaryOperator::DiceRoll;
+        let header = format!(
+            "{}Dice Roll with Modifiers: {} ({})",
+            self.indent(),
+            op.to_str().magenta(),
+            op.desc().blue()
+        );
+        let count_str = self.with_depth(|v| v.visit_expr(count));
+        let sides_str = self.with_depth(|v| v.visit_expr(sides));
+        let modifiers_str = self.with_depth(|v| {
+            modifiers
+                .iter()
+                .map(|modifier| {
+                    format!(
+                        "{}Modifier: {} ({})",
+                        v.indent(),
+                        modifier.to_string().magenta(),
+                        modifier.desc().blue()
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        });
+
+        format!(
+            "{}\n{}\n{}\n{}",
+            header, count_str, sides_str, modifiers_str
+        )
+    }
+
     fn visit_function_call(&mut self, name: &str, args: &[Expr]) -> Self::Output {
         let header = format!(
             "{}Function Call: {} ({} args)",
@@ -108,6 +156,39 @@ impl ExprVisitor for ExplainVisitor {
         let inner_str = self.with_depth(|v| v.visit_expr(expr));
         format!("{}\n{}", header, inner_str)
     }
+
+    fn visit_variable(&mut self, name: &str, host: bool) -> Self::Output {
+        if host {
+            format!(
+                "{}Variable: {} ({})",
+                self.indent(),
+                format!("@{}", name).magenta(),
+                "Host".blue()
+            )
+        } else {
+            format!("{}Variable: {}", self.indent(), name.magenta())
+        }
+    }
+
+    fn visit_let(&mut self, name: &str, value: &Expr, body: &Expr) -> Self::Output {
+        let header = format!("{}Let Binding: {}", self.indent(), name.magenta());
+        let value_str = self.with_depth(|v| v.visit_expr(value));
+        let body_str = self.with_depth(|v| v.visit_expr(body));
+        format!("{}\n{}\n{}", header, value_str, body_str)
+    }
+
+    fn visit_if(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> Self::Output {
+        let header = format!("{}Conditional:", self.indent());
+        let condition_str = self.with_depth(|v| v.visit_expr(condition));
+        let then_str = self.with_depth(|v| v.visit_expr(then_branch));
+        let else_str = self.with_depth(|v| v.visit_expr(else_branch));
+        format!("{}\n{}\n{}\n{}", header, condition_str, then_str, else_str)
+    }
 }
 
 /// Print explanation of the expression structure
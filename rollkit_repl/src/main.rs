@@ -1,5 +1,8 @@
 use ariadne::{Color, Label, Report, ReportKind, Source};
-use rollkit::{EvalError, Value, eval, parse};
+use rollkit::{
+    EvalError, RenderStyle, TypeError, eval_traced, parse, parsing::ParseError, render_trace,
+    typecheck,
+};
 use rustyline::{DefaultEditor, error::ReadlineError};
 use yansi::Paint;
 
@@ -16,16 +19,15 @@ use explain::explain_expr;
 // Yellow - results
 
 /// Report parse errors using ariadne
-fn report_parse_errors(seq: usize, input: &str, errors: Vec<chumsky::error::Rich<'_, char>>) {
+fn report_parse_errors(seq: usize, input: &str, errors: Vec<ParseError>) {
     for error in errors {
         let span = error.span();
-        let msg = error.to_string();
 
         Report::build(ReportKind::Error, ("<stdin>", span.into_range()))
             .with_message("Parse Error")
             .with_label(
-                Label::new(("<stdin>", span.start..span.end))
-                    .with_message(msg)
+                Label::new(("<stdin>", span.into_range()))
+                    .with_message(error.message())
                     .with_color(Color::Red),
             )
             .finish()
@@ -40,11 +42,14 @@ fn report_parse_errors(seq: usize, input: &str, errors: Vec<chumsky::error::Rich
 /// Report evaluation errors using ariadne
 fn report_eval_error(seq: usize, input: &str, error: EvalError) {
     let msg = error.to_string();
+    let span = error
+        .span()
+        .map_or(0..input.len(), |span| span.into_range());
 
-    Report::build(ReportKind::Error, ("<stdin>", 0..input.len()))
+    Report::build(ReportKind::Error, ("<stdin>", span.clone()))
         .with_message("Evaluation Error")
         .with_label(
-            Label::new(("<stdin>", 0..input.len()))
+            Label::new(("<stdin>", span))
                 .with_message(msg)
                 .with_color(Color::Red),
         )
@@ -56,24 +61,23 @@ fn report_eval_error(seq: usize, input: &str, error: EvalError) {
         .unwrap();
 }
 
-/// Print the result value
-fn format_expr_result(value: &Value) -> String {
-    match value {
-        Value::Integer(n) => format!("{}", n).yellow().to_string(),
-        Value::List(lst) => {
-            let sum: i64 = lst.iter().sum();
-            format!(
-                "{} (from list with {} elements: {{{}}})",
-                sum.yellow(),
-                lst.len().to_string().blue(),
-                lst.iter()
-                    .map(|n| n.to_string())
-                    .collect::<Vec<_>>()
-                    .join(", ")
-                    .blue()
-            )
-        }
-    }
+/// Report type errors using ariadne
+fn report_type_error(seq: usize, input: &str, error: TypeError) {
+    let span = error.span().into_range();
+
+    Report::build(ReportKind::Error, ("<stdin>", span.clone()))
+        .with_message("Type Error")
+        .with_label(
+            Label::new(("<stdin>", span))
+                .with_message(error.to_string())
+                .with_color(Color::Red),
+        )
+        .finish()
+        .print((
+            "<stdin>",
+            Source::from(input).with_display_line_offset(seq.saturating_sub(1)),
+        ))
+        .unwrap();
 }
 
 fn print_welcome() {
@@ -127,6 +131,14 @@ fn print_help() {
         "  {}              - Roll 2d6 and add 5",
         "2d6 + 5".magenta()
     );
+    println!(
+        "  {}                 - Roll 3d6, rolling again on 6s",
+        "3d6!".magenta()
+    );
+    println!(
+        "  {}             - Count dice rolling 8 or more",
+        "10d10>=8".magenta()
+    );
     println!(
         "  {}        - Roll dice from a list",
         "2d{1,2,3,5,8}".magenta()
@@ -150,15 +162,24 @@ fn print_err(err: &str) {
 fn eval_expr(seq: usize, expr: &str, with_explain: bool) {
     match parse(expr) {
         Ok(parsed_expr) => {
-            match eval(&parsed_expr) {
-                Ok(value) => {
+            // ill-typed expressions are rejected before rolling any dice
+            match typecheck(&parsed_expr).map(|_| eval_traced(&parsed_expr)) {
+                Err(e) => {
+                    report_type_error(seq, expr, e);
+                }
+                Ok(Ok(trace)) => {
+                    let style = if yansi::is_enabled() {
+                        RenderStyle::Ansi
+                    } else {
+                        RenderStyle::Plain
+                    };
                     println!(
                         "[{}] {}",
                         seq.to_string().green(),
-                        format_expr_result(&value)
+                        render_trace(expr, &trace, style)
                     );
                 }
-                Err(e) => {
+                Ok(Err(e)) => {
                     report_eval_error(seq, expr, e);
                 }
             }
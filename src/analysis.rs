@@ -0,0 +1,1269 @@
+//! Exact probability analysis of RollKit expressions.
+//!
+//! [`distribution`] computes the exact probability distribution of the result of an expression,
+//! without rolling any dice:
+//!
+//! ```
+//! # use rollkit::{analysis::distribution, parse};
+//! let dist = distribution(&parse("2d20kh1 + 5 >= 15").unwrap()).unwrap();
+//! // the chance of rolling 10 or higher on either of two d20s
+//! assert!((dist.probability(1) - (1.0 - 0.45 * 0.45)).abs() < 1e-12);
+//! ```
+//!
+//! The result of an expression is a list or an integer. Lists are summed up, like
+//! [`Value::sum`](crate::Value::sum) does. Not every expression can be analyzed exactly, e.g.,
+//! user-defined functions can do anything, and strong lists of dice are only supported in
+//! element-wise operations with constants. Such expressions result in an [`AnalysisError`].
+//!
+//! For such expressions, [`sample`] estimates the distribution by evaluating the expression many
+//! times with a seeded random number generator, optionally on multiple threads.
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use core::fmt;
+
+use crate::{
+    ast::{
+        BinaryOperator, ComparePoint, DiceModifier, ExplodeKind, Expr, ExprKind, ExprVisitor,
+        Literal, Span, UnaryOperator, walk_expr,
+    },
+    eval::{ArithmeticMode, EvalContext, EvalErrorKind, Modifiers, Value},
+};
+
+mod distribution;
+mod sampling;
+
+pub use distribution::Distribution;
+pub use sampling::{ConfidenceInterval, Estimate, SampleOptions, sample, sample_with_context};
+
+/// The kinds of [errors](AnalysisError) that can occur during analysis.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnalysisErrorKind {
+    /// The expression can't be analyzed exactly.
+    Unsupported {
+        /// Description of what can't be analyzed.
+        reason: &'static str,
+    },
+    /// The evaluation of the expression fails for some of the outcomes.
+    Eval(EvalErrorKind),
+    /// The distribution has too many outcomes to be computed.
+    TooComplex,
+}
+
+impl From<EvalErrorKind> for AnalysisErrorKind {
+    fn from(kind: EvalErrorKind) -> Self {
+        AnalysisErrorKind::Eval(kind)
+    }
+}
+
+impl fmt::Display for AnalysisErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnalysisErrorKind::Unsupported { reason } => {
+                write!(f, "Cannot analyze {} exactly", reason)
+            }
+            AnalysisErrorKind::Eval(kind) => write!(f, "The evaluation can fail: {}", kind),
+            AnalysisErrorKind::TooComplex => {
+                write!(f, "The distribution has too many outcomes to compute")
+            }
+        }
+    }
+}
+
+/// An error that occurred during analysis, with the span of the sub-expression that caused it.
+///
+/// # Example
+///
+/// ```
+/// # use rollkit::{analysis::{AnalysisErrorKind, distribution}, parse};
+/// let input = "1d6 + 1d20 / (1d2 - 1)";
+/// let error = distribution(&parse(input).unwrap()).unwrap_err();
+/// assert!(matches!(error.kind(), AnalysisErrorKind::Eval(_)));
+/// assert_eq!(&input[error.span().unwrap().into_range()], "1d20 / (1d2 - 1)");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnalysisError {
+    kind: AnalysisErrorKind,
+    span: Option<Span>,
+}
+
+impl AnalysisError {
+    /// Creates an error of the given kind, with the span of the sub-expression if known.
+    pub fn new(kind: AnalysisErrorKind, span: Option<Span>) -> Self {
+        Self { kind, span }
+    }
+
+    /// Returns the kind of the error.
+    pub fn kind(&self) -> &AnalysisErrorKind {
+        &self.kind
+    }
+
+    /// Converts the error into its kind.
+    pub fn into_kind(self) -> AnalysisErrorKind {
+        self.kind
+    }
+
+    /// Returns the span of the sub-expression that caused the error, if known.
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    /// Attaches the span to the error, unless it already has one from a more specific
+    /// sub-expression.
+    fn or_span(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+}
+
+impl From<AnalysisErrorKind> for AnalysisError {
+    fn from(kind: AnalysisErrorKind) -> Self {
+        Self::new(kind, None)
+    }
+}
+
+impl From<EvalErrorKind> for AnalysisError {
+    fn from(kind: EvalErrorKind) -> Self {
+        Self::new(kind.into(), None)
+    }
+}
+
+impl fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl core::error::Error for AnalysisError {}
+
+/// Creates the error for an expression that can't be analyzed.
+fn unsupported(reason: &'static str) -> AnalysisErrorKind {
+    AnalysisErrorKind::Unsupported { reason }
+}
+
+/// What is known about a list during analysis.
+#[derive(Debug, Clone)]
+enum ListOutcome {
+    /// A list of known elements. If not `ordered`, the elements are known but their order is
+    /// random, e.g., after keeping the highest elements.
+    Constant { elements: Vec<i64>, ordered: bool },
+    /// A list of a known number of independent elements with the same distribution.
+    Pool { count: usize, element: Distribution },
+    /// A list of which only the distribution of the sum is known.
+    Sum(Distribution),
+}
+
+impl ListOutcome {
+    /// Gets the distribution of the sum of the list.
+    fn sum(self, arith: ArithmeticMode) -> Result<Distribution, AnalysisErrorKind> {
+        match self {
+            ListOutcome::Constant { elements, .. } => {
+                Ok(Distribution::constant(arith.sum(elements)?))
+            }
+            ListOutcome::Pool { count, element } => element.sum_of(count, arith),
+            ListOutcome::Sum(sum) => Ok(sum),
+        }
+    }
+
+    /// Applies `f` to each element of the list.
+    fn map(
+        self,
+        f: impl Fn(i64) -> Result<i64, EvalErrorKind>,
+    ) -> Result<ListOutcome, AnalysisErrorKind> {
+        match self {
+            ListOutcome::Constant { elements, ordered } => Ok(ListOutcome::Constant {
+                elements: elements.into_iter().map(f).collect::<Result<_, _>>()?,
+                ordered,
+            }),
+            ListOutcome::Pool { count, element } => Ok(ListOutcome::Pool {
+                count,
+                element: element.map(f)?,
+            }),
+            ListOutcome::Sum(_) => Err(unsupported(
+                "element-wise operations on lists of unknown elements",
+            )),
+        }
+    }
+}
+
+/// What is known about a value during analysis, mirroring the values during evaluation.
+#[derive(Debug, Clone)]
+enum Outcome {
+    /// An integer.
+    Integer(Distribution),
+    /// A list, either strong or weak.
+    List { strong: bool, list: ListOutcome },
+}
+
+impl Outcome {
+    /// Asserts that the value is an integer and returns its distribution.
+    fn assert_integer(self) -> Result<Distribution, AnalysisErrorKind> {
+        match self {
+            Outcome::Integer(dist) => Ok(dist),
+            _ => Err(EvalErrorKind::IntegerExpected.into()),
+        }
+    }
+
+    /// Asserts that the value is a list and returns it with its strength.
+    fn assert_list(self) -> Result<(bool, ListOutcome), AnalysisErrorKind> {
+        match self {
+            Outcome::List { strong, list } => Ok((strong, list)),
+            _ => Err(EvalErrorKind::ListExpected.into()),
+        }
+    }
+
+    /// Tries to convert the value into an integer, summing up weak lists. If it's a strong list,
+    /// returns the list as an error.
+    fn try_into_integer(
+        self,
+        arith: ArithmeticMode,
+    ) -> Result<Result<Distribution, ListOutcome>, AnalysisErrorKind> {
+        match self {
+            Outcome::Integer(dist) => Ok(Ok(dist)),
+            Outcome::List {
+                strong: false,
+                list,
+            } => list.sum(arith).map(Ok),
+            Outcome::List { strong: true, list } => Ok(Err(list)),
+        }
+    }
+
+    /// Gets the distribution of the value as an integer, summing up lists.
+    fn into_sum(self, arith: ArithmeticMode) -> Result<Distribution, AnalysisErrorKind> {
+        match self {
+            Outcome::Integer(dist) => Ok(dist),
+            Outcome::List { list, .. } => list.sum(arith),
+        }
+    }
+
+    /// Applies `op` to an integer, or element-wise to a strong list, like
+    /// [`InnerValue::map_elementwise`](crate::eval) does.
+    fn map_elementwise(
+        self,
+        arith: ArithmeticMode,
+        op: impl Fn(i64) -> Result<i64, EvalErrorKind>,
+    ) -> Result<Outcome, AnalysisErrorKind> {
+        Ok(match self.try_into_integer(arith)? {
+            Ok(dist) => Outcome::Integer(dist.map(op)?),
+            Err(list) => Outcome::List {
+                strong: true,
+                list: list.map(op)?,
+            },
+        })
+    }
+
+    /// Returns `true` if the value is known exactly, up to the order of list elements.
+    fn is_constant(&self) -> bool {
+        match self {
+            Outcome::Integer(dist) => dist.as_constant().is_some(),
+            Outcome::List { list, .. } => matches!(list, ListOutcome::Constant { .. }),
+        }
+    }
+
+    /// Creates a strong list from a list outcome.
+    fn strong(list: ListOutcome) -> Outcome {
+        Outcome::List { strong: true, list }
+    }
+
+    /// Creates a weak list from a list outcome.
+    fn weak(list: ListOutcome) -> Outcome {
+        Outcome::List {
+            strong: false,
+            list,
+        }
+    }
+}
+
+/// Analyzes arithmetic and comparison operations on integers and lists, following the rules of
+/// evaluation. Integers are combined assuming they're independent. Strong lists only support
+/// operations with constant integers, or with other lists of known elements.
+fn analyze_elementwise_op(
+    arith: ArithmeticMode,
+    left: Outcome,
+    right: Outcome,
+    op: impl Fn(i64, i64) -> Result<i64, EvalErrorKind>,
+) -> Result<Outcome, AnalysisErrorKind> {
+    const RANDOM_WITH_LIST: &str = "operations between strong lists and random integers";
+
+    match (
+        left.try_into_integer(arith)?,
+        right.try_into_integer(arith)?,
+    ) {
+        (Ok(l), Ok(r)) => Ok(Outcome::Integer(l.combine(&r, op)?)),
+        (Ok(l), Err(list)) => {
+            let l = l.as_constant().ok_or(unsupported(RANDOM_WITH_LIST))?;
+            Ok(Outcome::strong(list.map(|r| op(l, r))?))
+        }
+        (Err(list), Ok(r)) => {
+            let r = r.as_constant().ok_or(unsupported(RANDOM_WITH_LIST))?;
+            Ok(Outcome::strong(list.map(|l| op(l, r))?))
+        }
+        (
+            Err(ListOutcome::Constant {
+                elements: l,
+                ordered: true,
+            }),
+            Err(ListOutcome::Constant {
+                elements: r,
+                ordered: true,
+            }),
+        ) => {
+            if l.len() != r.len() {
+                return Err(EvalErrorKind::ListMismatch {
+                    left_len: l.len(),
+                    right_len: r.len(),
+                }
+                .into());
+            }
+            let elements = l
+                .into_iter()
+                .zip(r)
+                .map(|(l, r)| op(l, r))
+                .collect::<Result<_, _>>()?;
+            Ok(Outcome::strong(ListOutcome::Constant {
+                elements,
+                ordered: true,
+            }))
+        }
+        _ => Err(unsupported("operations between strong lists of dice")),
+    }
+}
+
+/// Analyzes arithmetic operations with [`analyze_elementwise_op`].
+fn analyze_arith_op(
+    arith: ArithmeticMode,
+    left: Outcome,
+    right: Outcome,
+    op: fn(ArithmeticMode, i64, i64) -> Result<i64, EvalErrorKind>,
+) -> Result<Outcome, AnalysisErrorKind> {
+    analyze_elementwise_op(arith, left, right, |l, r| op(arith, l, r))
+}
+
+/// A wrapper macro to create "0-1" comparison operations.
+macro_rules! bi_cmp_op {
+    ($op:tt) => {
+        |a: i64, b: i64| Ok(if a $op b { 1 } else { 0 })
+    };
+}
+
+/// Counts the references to a variable bound by `let` in an expression, except those to inner
+/// bindings of the same name.
+fn count_references(expr: &Expr, name: &str) -> usize {
+    match &expr.kind {
+        ExprKind::Literal(_) => 0,
+        ExprKind::UnaryOp { expr, .. } | ExprKind::StrongList(expr) => count_references(expr, name),
+        ExprKind::BinaryOp { left, right, .. }
+        | ExprKind::ModifiedDice {
+            count: left,
+            sides: right,
+            ..
+        } => count_references(left, name) + count_references(right, name),
+        ExprKind::FunctionCall { args, .. } => {
+            args.iter().map(|arg| count_references(arg, name)).sum()
+        }
+        ExprKind::Variable { name: var, host } => usize::from(!host && var == name),
+        ExprKind::Let {
+            name: bound,
+            value,
+            body,
+        } => {
+            let in_body = if bound == name {
+                0
+            } else {
+                count_references(body, name)
+            };
+            count_references(value, name) + in_body
+        }
+        ExprKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            count_references(condition, name)
+                + count_references(then_branch, name)
+                + count_references(else_branch, name)
+        }
+    }
+}
+
+/// The visitor computing what is known about the value of each expression node.
+struct AnalysisVisitor<'a> {
+    context: &'a EvalContext,
+    depth: usize,
+    /// What is known about the variables bound by `let`, innermost last.
+    scope: Vec<(String, Outcome)>,
+}
+
+impl AnalysisVisitor<'_> {
+    /// Analyzes keep/drop operations on lists.
+    fn keep_drop(
+        &self,
+        left: Outcome,
+        right: Outcome,
+        keep: bool,
+        highest: bool,
+    ) -> Result<Outcome, AnalysisErrorKind> {
+        let arith = self.context.arithmetic;
+        let (strong, list) = left.assert_list()?;
+        let requested = right.assert_integer()?;
+
+        let available = match &list {
+            ListOutcome::Constant { elements, .. } => elements.len(),
+            ListOutcome::Pool { count, .. } => *count,
+            ListOutcome::Sum(_) => {
+                return Err(unsupported(
+                    "keeping or dropping from lists of unknown length",
+                ));
+            }
+        };
+
+        let mut parts = Vec::new();
+        for (requested, p) in requested.iter() {
+            if requested < 0 {
+                return Err(if keep {
+                    EvalErrorKind::KeepTooLess { requested }
+                } else {
+                    EvalErrorKind::DropTooLess { requested }
+                }
+                .into());
+            }
+            if requested as usize > available {
+                return Err(if keep {
+                    EvalErrorKind::KeepTooMany {
+                        available,
+                        requested,
+                    }
+                } else {
+                    EvalErrorKind::DropTooMany {
+                        available,
+                        requested,
+                    }
+                }
+                .into());
+            }
+
+            // dropping the highest is keeping the lowest of the rest
+            let (kept, highest) = if keep {
+                (requested as usize, highest)
+            } else {
+                (available - requested as usize, !highest)
+            };
+            parts.push((p, kept, highest));
+        }
+
+        match (list, &parts[..]) {
+            (ListOutcome::Constant { mut elements, .. }, &[(_, kept, highest)]) => {
+                elements.sort_unstable_by(|a, b| if highest { b.cmp(a) } else { a.cmp(b) });
+                elements.truncate(kept);
+                Ok(Outcome::List {
+                    strong,
+                    list: ListOutcome::Constant {
+                        elements,
+                        ordered: false,
+                    },
+                })
+            }
+            (list, parts) => {
+                let sums = parts.iter().map(|&(p, kept, highest)| {
+                    let sum = match &list {
+                        ListOutcome::Constant { elements, .. } => {
+                            let mut elements = elements.clone();
+                            elements
+                                .sort_unstable_by(|a, b| if highest { b.cmp(a) } else { a.cmp(b) });
+                            Distribution::constant(arith.sum(elements.into_iter().take(kept))?)
+                        }
+                        ListOutcome::Pool { count, element } => {
+                            element.sum_of_extremes(*count, kept, highest, arith)?
+                        }
+                        ListOutcome::Sum(_) => unreachable!(),
+                    };
+                    Ok((p, sum))
+                });
+                let sum =
+                    Distribution::mixture(sums.collect::<Result<Vec<_>, AnalysisErrorKind>>()?)?;
+                Ok(Outcome::List {
+                    strong,
+                    list: ListOutcome::Sum(sum),
+                })
+            }
+        }
+    }
+
+    /// Analyzes a dice roll, with the modifiers applied.
+    fn roll_dice(
+        &self,
+        count: Outcome,
+        sides: Outcome,
+        modifiers: &[DiceModifier],
+    ) -> Result<Outcome, AnalysisErrorKind> {
+        let arith = self.context.arithmetic;
+        let count = count.assert_integer()?;
+        for (count, _) in count.iter() {
+            if count < 0 {
+                return Err(EvalErrorKind::NegativeDiceCount { count }.into());
+            }
+            if usize::try_from(count).is_ok_and(|count| count > self.context.limits.max_dice) {
+                return Err(EvalErrorKind::TooManyDice {
+                    limit: self.context.limits.max_dice,
+                    requested: count,
+                }
+                .into());
+            }
+        }
+
+        // the possible dice, and their probabilities
+        let dice: Vec<(f64, Die)> = match sides {
+            Outcome::Integer(sides) => sides
+                .iter()
+                .map(|(sides, p)| {
+                    if sides < 1 {
+                        return Err(EvalErrorKind::InvalidSides { sides }.into());
+                    }
+                    let faces = Distribution::uniform(1..=sides)?;
+                    Ok((p, self.die(faces, sides, modifiers)?))
+                })
+                .collect::<Result<_, AnalysisErrorKind>>()?,
+            Outcome::List {
+                list: ListOutcome::Constant { elements, .. },
+                ..
+            } => {
+                let max_face = elements
+                    .iter()
+                    .copied()
+                    .max()
+                    .ok_or(EvalErrorKind::EmptyDie)?;
+                let faces = Distribution::uniform(elements.into_iter())?;
+                vec![(1.0, self.die(faces, max_face, modifiers)?)]
+            }
+            Outcome::List { .. } => return Err(unsupported("dice with random faces")),
+        };
+
+        if let (Some(count), [(_, die)]) = (count.as_constant(), &dice[..]) {
+            let count = count as usize;
+            return Ok(match die {
+                Die::Element(element) => Outcome::weak(ListOutcome::Pool {
+                    count,
+                    element: element.clone(),
+                }),
+                Die::Elements(sum) => Outcome::weak(ListOutcome::Sum(sum.sum_of(count, arith)?)),
+                Die::Score(score) => Outcome::Integer(score.sum_of(count, arith)?),
+            });
+        }
+
+        let mut parts = Vec::new();
+        let mut successes = false;
+        for (count, count_p) in count.iter() {
+            for (die_p, die) in &dice {
+                let (Die::Element(dist) | Die::Elements(dist) | Die::Score(dist)) = die;
+                successes = matches!(die, Die::Score(_));
+                parts.push((count_p * die_p, dist.sum_of(count as usize, arith)?));
+            }
+        }
+        let sum = Distribution::mixture(parts)?;
+        Ok(if successes {
+            Outcome::Integer(sum)
+        } else {
+            Outcome::weak(ListOutcome::Sum(sum))
+        })
+    }
+
+    /// Analyzes rolling a single die with the given faces and modifiers.
+    fn die(
+        &self,
+        faces: Distribution,
+        max_face: i64,
+        modifiers: &[DiceModifier],
+    ) -> Result<Die, AnalysisErrorKind> {
+        let context = self.context;
+        let arith = context.arithmetic;
+        let modifiers = Modifiers::new(modifiers, max_face);
+        let matches_any =
+            |conditions: &[ComparePoint], face| conditions.iter().any(|c| c.matches(face));
+
+        // rerolling until the roll doesn't match is rolling a die without the matching faces
+        let rerolled = faces.mass(|face| matches_any(&modifiers.reroll, face));
+        let Some(kept) = faces.filter(|face| !matches_any(&modifiers.reroll, face)) else {
+            return Err(EvalErrorKind::UnsatisfiableReroll.into());
+        };
+        if rerolled >= 1.0 {
+            return Err(EvalErrorKind::UnsatisfiableReroll.into());
+        }
+        let mut limit_p = 1.0;
+        for _ in 0..=context.reroll_limit {
+            limit_p *= rerolled;
+        }
+        if limit_p > f64::EPSILON {
+            return Err(EvalErrorKind::RerollLimitExceeded {
+                limit: context.reroll_limit,
+            }
+            .into());
+        }
+        let kept = kept.scale(1.0 / (1.0 - rerolled));
+
+        // rerolling once, the second roll is rerolled only by the unlimited rerolls
+        let once = kept.mass(|face| matches_any(&modifiers.reroll_once, face));
+        let not_once = kept.filter(|face| !matches_any(&modifiers.reroll_once, face));
+        let roll = Distribution::mixture(
+            not_once
+                .map(|dist| (1.0, dist))
+                .into_iter()
+                .chain([(once, kept)]),
+        )?;
+
+        let counting = !modifiers.success.is_empty();
+        if modifiers.explode.is_empty() {
+            return Ok(if counting {
+                Die::Score(roll.map(|face| Ok(modifiers.score(face)))?)
+            } else {
+                Die::Element(roll)
+            });
+        }
+
+        // the contribution of a roll to the result, given whether it's an extra roll
+        let contribution = |face: i64, extra: bool| -> Result<i64, EvalErrorKind> {
+            let element = match modifiers.explode_kind {
+                ExplodeKind::Penetrating if extra => arith.sub(face, 1)?,
+                _ => face,
+            };
+            Ok(match modifiers.explode_kind {
+                ExplodeKind::Standard | ExplodeKind::Penetrating if counting => {
+                    modifiers.score(element)
+                }
+                _ => element,
+            })
+        };
+
+        // the total contribution of a roll and its explosions, with `k` explosions left
+        let exploding = roll.filter(|face| modifiers.explodes(face));
+        let stopping = roll.filter(|face| !modifiers.explodes(face));
+        let total = |extra: bool, next: Option<&Distribution>| match (next, &exploding) {
+            (Some(next), Some(exploding)) => {
+                let exploded = exploding
+                    .map(|face| contribution(face, extra))?
+                    .combine(next, |l, r| arith.add(l, r))?;
+                let stopped = match &stopping {
+                    Some(stopping) => Some((1.0, stopping.map(|face| contribution(face, extra))?)),
+                    None => None,
+                };
+                Distribution::mixture(stopped.into_iter().chain([(1.0, exploded)]))
+            }
+            _ => roll.map(|face| contribution(face, extra)),
+        };
+        let mut extra = None;
+        for _ in 0..context.explode_limit {
+            extra = Some(total(true, extra.as_ref())?);
+        }
+        let total = total(false, extra.as_ref())?;
+
+        Ok(match modifiers.explode_kind {
+            ExplodeKind::Compounding if counting => {
+                Die::Score(total.map(|face| Ok(modifiers.score(face)))?)
+            }
+            ExplodeKind::Compounding => Die::Element(total),
+            _ if counting => Die::Score(total),
+            _ => Die::Elements(total),
+        })
+    }
+}
+
+/// What is known about rolling a single die with modifiers.
+enum Die {
+    /// The die results in a single element.
+    Element(Distribution),
+    /// The die results in a varying number of elements due to explosions, of which only the
+    /// distribution of the sum is known.
+    Elements(Distribution),
+    /// The die is counted as a success or failure, with the distribution of its score.
+    Score(Distribution),
+}
+
+impl ExprVisitor for AnalysisVisitor<'_> {
+    type Output = Result<Outcome, AnalysisError>;
+
+    fn visit_expr(&mut self, expr: &Expr) -> Self::Output {
+        if self.depth >= self.context.limits.max_depth {
+            return Err(AnalysisError::new(
+                EvalErrorKind::DepthLimitExceeded {
+                    limit: self.context.limits.max_depth,
+                }
+                .into(),
+                Some(expr.span),
+            ));
+        }
+        self.depth += 1;
+        let result = walk_expr(self, expr);
+        self.depth -= 1;
+
+        // errors from sub-expressions already carry their spans
+        result.map_err(|error| error.or_span(expr.span))
+    }
+
+    fn visit_literal(&mut self, literal: &Literal) -> Self::Output {
+        Ok(match literal {
+            Literal::Int(n) => Outcome::Integer(Distribution::constant(*n)),
+            Literal::List(lst) => Outcome::weak(ListOutcome::Constant {
+                elements: lst.clone(),
+                ordered: true,
+            }),
+            Literal::Range(range) => {
+                let elements: Vec<i64> = range
+                    .to_iter()
+                    .take(self.context.limits.max_list_len.saturating_add(1))
+                    .collect();
+                if elements.len() > self.context.limits.max_list_len {
+                    return Err(EvalErrorKind::ListTooLong {
+                        limit: self.context.limits.max_list_len,
+                        len: elements.len(),
+                    }
+                    .into());
+                }
+                Outcome::weak(ListOutcome::Constant {
+                    elements,
+                    ordered: true,
+                })
+            }
+        })
+    }
+
+    fn visit_unary_op(&mut self, op: &UnaryOperator, expr: &Expr) -> Self::Output {
+        let arith = self.context.arithmetic;
+        let value = self.visit_expr(expr)?;
+
+        Ok(match op {
+            UnaryOperator::Negation => value.map_elementwise(arith, |i| arith.neg(i)),
+            UnaryOperator::Plus => value.map_elementwise(arith, Ok),
+        }?)
+    }
+
+    fn visit_binary_op(&mut self, left: &Expr, op: &BinaryOperator, right: &Expr) -> Self::Output {
+        let arith = self.context.arithmetic;
+        let left = self.visit_expr(left)?;
+        let right = self.visit_expr(right)?;
+
+        Ok(match op {
+            BinaryOperator::DiceRoll => self.roll_dice(left, right, &[]),
+            BinaryOperator::KeepHighest => self.keep_drop(left, right, true, true),
+            BinaryOperator::KeepLowest => self.keep_drop(left, right, true, false),
+            BinaryOperator::DropHighest => self.keep_drop(left, right, false, true),
+            BinaryOperator::DropLowest => self.keep_drop(left, right, false, false),
+            BinaryOperator::Exponentiation => {
+                analyze_arith_op(arith, left, right, ArithmeticMode::pow)
+            }
+            BinaryOperator::Multiplication => {
+                analyze_arith_op(arith, left, right, ArithmeticMode::mul)
+            }
+            BinaryOperator::Division => analyze_arith_op(arith, left, right, ArithmeticMode::div),
+            BinaryOperator::FloorDivision => {
+                analyze_arith_op(arith, left, right, ArithmeticMode::div_floor)
+            }
+            BinaryOperator::CeilDivision => {
+                analyze_arith_op(arith, left, right, ArithmeticMode::div_ceil)
+            }
+            BinaryOperator::Modulo => analyze_arith_op(arith, left, right, ArithmeticMode::rem),
+            BinaryOperator::Addition => analyze_arith_op(arith, left, right, ArithmeticMode::add),
+            BinaryOperator::Subtraction => {
+                analyze_arith_op(arith, left, right, ArithmeticMode::sub)
+            }
+            BinaryOperator::Equal => analyze_elementwise_op(arith, left, right, bi_cmp_op!(==)),
+            BinaryOperator::NotEqual => analyze_elementwise_op(arith, left, right, bi_cmp_op!(!=)),
+            BinaryOperator::LessThan => analyze_elementwise_op(arith, left, right, bi_cmp_op!(<)),
+            BinaryOperator::LessEqual => analyze_elementwise_op(arith, left, right, bi_cmp_op!(<=)),
+            BinaryOperator::GreaterThan => {
+                analyze_elementwise_op(arith, left, right, bi_cmp_op!(>))
+            }
+            BinaryOperator::GreaterEqual => {
+                analyze_elementwise_op(arith, left, right, bi_cmp_op!(>=))
+            }
+        }?)
+    }
+
+    fn visit_modified_dice(
+        &mut self,
+        count: &Expr,
+        sides: &Expr,
+        modifiers: &[DiceModifier],
+    ) -> Self::Output {
+        let count = self.visit_expr(count)?;
+        let sides = self.visit_expr(sides)?;
+        Ok(self.roll_dice(count, sides, modifiers)?)
+    }
+
+    fn visit_function_call(&mut self, name: &str, args: &[Expr]) -> Self::Output {
+        let arith = self.context.arithmetic;
+        if self.context.functions.contains(name) {
+            return Err(unsupported("user-defined functions").into());
+        }
+        if !crate::eval::is_builtin(name) {
+            return Err(EvalErrorKind::UnknownFunction {
+                name: name.to_string(),
+            }
+            .into());
+        }
+
+        let args = args
+            .iter()
+            .map(|arg| self.visit_expr(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let wrong_arguments = |expected| EvalErrorKind::WrongArguments {
+            name: name.to_string(),
+            expected,
+        };
+
+        Ok(match (name, <[Outcome; 1]>::try_from(args)) {
+            ("abs", Ok([arg])) => arg.map_elementwise(arith, |i| arith.abs(i))?,
+            ("abs", Err(_)) => return Err(wrong_arguments("abs(value)").into()),
+            ("sum", Ok([arg])) => Outcome::Integer(arg.into_sum(arith)?),
+            ("sum", Err(_)) => return Err(wrong_arguments("sum(value)").into()),
+            ("len", Ok([arg])) => match arg.assert_list() {
+                Ok((_, ListOutcome::Constant { elements, .. })) => {
+                    Outcome::Integer(Distribution::constant(elements.len() as i64))
+                }
+                Ok((_, ListOutcome::Pool { count, .. })) => {
+                    Outcome::Integer(Distribution::constant(count as i64))
+                }
+                Ok((_, ListOutcome::Sum(_))) => {
+                    return Err(unsupported("the length of lists of unknown length").into());
+                }
+                Err(_) => return Err(wrong_arguments("len(list)").into()),
+            },
+            ("len", Err(_)) => return Err(wrong_arguments("len(list)").into()),
+            (name @ ("max" | "min"), Ok([arg])) => {
+                let highest = name == "max";
+                match arg {
+                    Outcome::Integer(dist) => Outcome::Integer(dist),
+                    Outcome::List { list, .. } => {
+                        let empty = || EvalErrorKind::EmptyList {
+                            name: name.to_string(),
+                        };
+                        Outcome::Integer(match list {
+                            ListOutcome::Constant { elements, .. } => {
+                                let extreme = if highest {
+                                    elements.into_iter().max()
+                                } else {
+                                    elements.into_iter().min()
+                                };
+                                Distribution::constant(extreme.ok_or_else(empty)?)
+                            }
+                            ListOutcome::Pool { count: 0, .. } => return Err(empty().into()),
+                            ListOutcome::Pool { count, element } => {
+                                element.sum_of_extremes(count, 1, highest, arith)?
+                            }
+                            ListOutcome::Sum(_) => {
+                                return Err(unsupported(
+                                    "the extremes of lists of unknown elements",
+                                )
+                                .into());
+                            }
+                        })
+                    }
+                }
+            }
+            (name @ ("max" | "min"), Err(args)) if !args.is_empty() => {
+                let pick = if name == "max" { i64::max } else { i64::min };
+                let mut result: Option<Distribution> = None;
+                for arg in args {
+                    let dist = arg.try_into_integer(arith)?.map_err(|_| {
+                        wrong_arguments(if name == "max" {
+                            "max(list) or max(value, value, ...)"
+                        } else {
+                            "min(list) or min(value, value, ...)"
+                        })
+                    })?;
+                    result = Some(match result {
+                        Some(result) => result.combine(&dist, |l, r| Ok(pick(l, r)))?,
+                        None => dist,
+                    });
+                }
+                Outcome::Integer(result.unwrap())
+            }
+            _ => return Err(unsupported("this built-in function").into()),
+        })
+    }
+
+    fn visit_strong_list(&mut self, expr: &Expr) -> Self::Output {
+        Ok(match self.visit_expr(expr)? {
+            Outcome::Integer(dist) => match dist.as_constant() {
+                Some(i) => Outcome::weak(ListOutcome::Constant {
+                    elements: vec![i],
+                    ordered: true,
+                }),
+                None => Outcome::weak(ListOutcome::Pool {
+                    count: 1,
+                    element: dist,
+                }),
+            },
+            Outcome::List { list, .. } => Outcome::strong(list),
+        })
+    }
+
+    fn visit_variable(&mut self, name: &str, host: bool) -> Self::Output {
+        let outcome = if host {
+            self.context.variables.get(name).map(|value| match value {
+                Value::Integer(i) => Outcome::Integer(Distribution::constant(*i)),
+                Value::List(lst) => Outcome::weak(ListOutcome::Constant {
+                    elements: lst.clone(),
+                    ordered: true,
+                }),
+            })
+        } else {
+            let mut bindings = self.scope.iter().rev();
+            let binding = bindings.find(|(bound, _)| bound == name);
+            binding.map(|(_, outcome)| outcome.clone())
+        };
+        outcome.ok_or_else(|| {
+            let name = if host {
+                format!("@{}", name)
+            } else {
+                name.to_string()
+            };
+            EvalErrorKind::UnboundVariable { name }.into()
+        })
+    }
+
+    fn visit_let(&mut self, name: &str, value: &Expr, body: &Expr) -> Self::Output {
+        let value = self.visit_expr(value)?;
+        // the references to a random value are not independent
+        if !value.is_constant() && count_references(body, name) > 1 {
+            return Err(unsupported("variables of random values used more than once").into());
+        }
+        self.scope.push((name.to_string(), value));
+        let result = self.visit_expr(body);
+        self.scope.pop();
+        result
+    }
+
+    fn visit_if(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> Self::Output {
+        let arith = self.context.arithmetic;
+        let condition = self.visit_expr(condition)?.try_into_integer(arith)?;
+        let p_false = condition
+            .map_err(|_| EvalErrorKind::IntegerExpected)?
+            .probability(0);
+        // like in the evaluation, branches that are never taken are not analyzed
+        if p_false == 0.0 {
+            return self.visit_expr(then_branch);
+        } else if p_false == 1.0 {
+            return self.visit_expr(else_branch);
+        }
+        let parts = [(1.0 - p_false, then_branch), (p_false, else_branch)];
+        let mut outcomes = Vec::with_capacity(2);
+        for (p, branch) in parts {
+            outcomes.push((p, self.visit_expr(branch)?));
+        }
+        let integers = outcomes
+            .iter()
+            .all(|(_, outcome)| matches!(outcome, Outcome::Integer(_)));
+        let strong = outcomes
+            .iter()
+            .map(|(_, outcome)| matches!(outcome, Outcome::List { strong: true, .. }))
+            .collect::<Vec<_>>();
+        if strong[0] != strong[1] {
+            return Err(unsupported("conditionals with branches of different types").into());
+        }
+        let parts = outcomes
+            .into_iter()
+            .map(|(p, outcome)| Ok((p, outcome.into_sum(arith)?)));
+        let dist = Distribution::mixture(parts.collect::<Result<Vec<_>, AnalysisErrorKind>>()?)?;
+        // integers mixed with weak lists are kept as lists, summed up when used as integers
+        Ok(if integers {
+            Outcome::Integer(dist)
+        } else {
+            Outcome::List {
+                strong: strong[0],
+                list: ListOutcome::Sum(dist),
+            }
+        })
+    }
+}
+
+/// Computes the exact probability distribution of the result of a RollKit expression, with the
+/// default [evaluation context](EvalContext). See [`distribution_with_context`] for details.
+///
+/// # Examples
+///
+/// ```
+/// # use rollkit::{analysis::distribution, parse};
+/// let dist = distribution(&parse("4d6kh3").unwrap()).unwrap();
+/// assert!((dist.probability(18) - 21.0 / 1296.0).abs() < 1e-12);
+/// assert!((dist.probability(3) - 1.0 / 1296.0).abs() < 1e-12);
+///
+/// assert!(distribution(&parse("{4d6} + 1d4").unwrap()).is_err());
+/// ```
+pub fn distribution(expr: &Expr) -> Result<Distribution, AnalysisError> {
+    distribution_with_context(expr, &EvalContext::default())
+}
+
+/// Computes the exact probability distribution of the result of a RollKit expression, as if
+/// evaluated with the given [evaluation context](EvalContext). Lists are summed up.
+///
+/// Random integers are combined assuming they're independent, which holds as every sub-expression
+/// rolls its own dice, unless it refers to a variable bound by `let` used elsewhere. Keep and drop
+/// operations on dice are analyzed with order statistics, so `20d20kh10` is as feasible as
+/// `20d20`. Explosions are followed up to [`EvalContext::explode_limit`]. Conditionals mix the
+/// distributions of their branches, weighted by the probability of the condition, skipping
+/// branches that are never taken.
+///
+/// The analysis fails with an [`AnalysisError`] if:
+///
+/// - The expression contains constructs that can't be analyzed exactly, e.g., calls of
+///   user-defined functions, arithmetic between a strong list of dice and a random integer, or
+///   variables bound to random values used more than once, like `let x = 1d6; x * x`, as the
+///   references are not independent.
+/// - The evaluation fails for some outcomes, e.g., `1d6 / (1d2 - 1)` divides by zero half the
+///   time. Exceeding the reroll limit of the context counts as failing, unless it's less likely
+///   than the precision of [`f64`].
+/// - The distribution has too many outcomes, e.g., `1d1000000000`.
+///
+/// # Examples
+///
+/// ```
+/// # use rollkit::{EvalContext, analysis::distribution_with_context, parse};
+/// let context = EvalContext { explode_limit: 1, ..Default::default() };
+/// let dist = distribution_with_context(&parse("1d6!").unwrap(), &context).unwrap();
+/// assert!((dist.probability(12) - 1.0 / 36.0).abs() < 1e-12);
+/// assert_eq!(dist.probability(13), 0.0);
+/// ```
+pub fn distribution_with_context(
+    expr: &Expr,
+    context: &EvalContext,
+) -> Result<Distribution, AnalysisError> {
+    let mut visitor = AnalysisVisitor {
+        context,
+        depth: 0,
+        scope: Vec::new(),
+    };
+    let outcome = visitor.visit_expr(expr)?;
+    outcome
+        .into_sum(context.arithmetic)
+        .map_err(|kind| AnalysisError::new(kind, Some(expr.span)))
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{eval_with, parse};
+
+    fn analyze(input: &str) -> Result<Distribution, AnalysisErrorKind> {
+        distribution(&parse(input).unwrap()).map_err(AnalysisError::into_kind)
+    }
+
+    /// Asserts that the distribution of the input has the given outcomes and weights.
+    fn assert_dist(input: &str, expected: &[(i64, f64)]) {
+        let dist = analyze(input).unwrap_or_else(|e| panic!("Input: {}: {:?}", input, e));
+        let total: f64 = expected.iter().map(|(_, w)| w).sum();
+        assert_eq!(dist.iter().len(), expected.len(), "Input: {}", input);
+        for ((value, p), &(expected_value, weight)) in dist.iter().zip(expected) {
+            assert_eq!(value, expected_value, "Input: {}", input);
+            assert!(
+                (p - weight / total).abs() < 1e-12,
+                "Input: {}, outcome {}: {} != {}",
+                input,
+                value,
+                p,
+                weight / total
+            );
+        }
+    }
+
+    #[test]
+    fn test_distribution() {
+        assert_dist("3", &[(3, 1.0)]);
+        assert_dist("[1, 3]", &[(6, 1.0)]);
+        assert_dist("1d4 + 1", &[(2, 1.0), (3, 1.0), (4, 1.0), (5, 1.0)]);
+        assert_dist("2d3", &[(2, 1.0), (3, 2.0), (4, 3.0), (5, 2.0), (6, 1.0)]);
+        assert_dist("1d{1, 1, 2}", &[(1, 2.0), (2, 1.0)]);
+        assert_dist("1d6 >= 5", &[(0, 4.0), (1, 2.0)]);
+        assert_dist("2d2kh1", &[(1, 1.0), (2, 3.0)]);
+        assert_dist("2d2kl1", &[(1, 3.0), (2, 1.0)]);
+        assert_dist("3d2dl1", &[(2, 1.0), (3, 3.0), (4, 4.0)]);
+        assert_dist("max(2d2)", &[(1, 1.0), (2, 3.0)]);
+        assert_dist("max(1d2, 1d2)", &[(1, 1.0), (2, 3.0)]);
+        assert_dist("10000d6kh3", &[(18, 1.0)]);
+        assert_dist("max(10000d6)", &[(6, 1.0)]);
+        assert_dist("{2d2} * 2", &[(4, 1.0), (6, 2.0), (8, 1.0)]);
+        assert_dist("{3d6} >= 5", &[(0, 8.0), (1, 12.0), (2, 6.0), (3, 1.0)]);
+        assert_dist("3d6>=5", &[(0, 8.0), (1, 12.0), (2, 6.0), (3, 1.0)]);
+        assert_dist("1d6>=5f1", &[(-1, 1.0), (0, 3.0), (1, 2.0)]);
+        assert_dist("(1d2 + 0)d2", &[(1, 2.0), (2, 3.0), (3, 2.0), (4, 1.0)]);
+        assert_dist("1d(1d2 + 0)", &[(1, 3.0), (2, 1.0)]);
+        assert_dist("1d4r1", &[(2, 1.0), (3, 1.0), (4, 1.0)]);
+        assert_dist("1d4ro1", &[(1, 1.0), (2, 5.0), (3, 5.0), (4, 5.0)]);
+        assert_dist("1d2kh(1d2 - 1)", &[(0, 2.0), (1, 1.0), (2, 1.0)]);
+        assert_dist("[1, 3]kh2", &[(5, 1.0)]);
+        assert_dist("abs(1d3 - 2)", &[(0, 1.0), (1, 2.0)]);
+        assert_dist("let x = 1d2; x + 1d2", &[(2, 1.0), (3, 2.0), (4, 1.0)]);
+        assert_dist("let x = 3; let y = 1d2; x * y", &[(3, 1.0), (6, 1.0)]);
+        assert_dist("let x = 1d2; let x = 3; x + x", &[(6, 1.0)]);
+        assert_dist(
+            "if 1d2 == 2 then 10 else 1d2",
+            &[(1, 1.0), (2, 1.0), (10, 2.0)],
+        );
+        assert_dist(
+            "if 1d2 == 2 then 2d2 else {1}",
+            &[(1, 4.0), (2, 1.0), (3, 2.0), (4, 1.0)],
+        );
+        assert_dist(
+            "if 1d2 > 0 then 1d2 else {2d6} + 1d6",
+            &[(1, 1.0), (2, 1.0)],
+        );
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut variables = BTreeMap::new();
+        variables.insert("mod".to_string(), Value::Integer(2));
+        variables.insert("pool".to_string(), Value::List(vec![1, 3]));
+        let context = EvalContext {
+            variables,
+            ..Default::default()
+        };
+        let analyze = |input: &str| {
+            distribution_with_context(&parse(input).unwrap(), &context)
+                .map(|dist| dist.iter().collect::<Vec<_>>())
+                .map_err(AnalysisError::into_kind)
+        };
+
+        assert_eq!(analyze("1d1 + @mod"), Ok(vec![(3, 1.0)]));
+        assert_eq!(analyze("{@pool} * @mod"), Ok(vec![(8, 1.0)]));
+        assert_eq!(
+            analyze("@dex"),
+            Err(AnalysisErrorKind::Eval(EvalErrorKind::UnboundVariable {
+                name: "@dex".into()
+            }))
+        );
+        // references to the same random value are not independent
+        assert!(matches!(
+            analyze("let x = 1d6; x * x"),
+            Err(AnalysisErrorKind::Unsupported { .. })
+        ));
+        assert!(matches!(
+            analyze("let x = 1d6; let y = x; max(y, y)"),
+            Err(AnalysisErrorKind::Unsupported { .. })
+        ));
+    }
+
+    #[test]
+    fn test_explode() {
+        let context = EvalContext {
+            explode_limit: 2,
+            ..Default::default()
+        };
+        let analyze = |input: &str| distribution_with_context(&parse(input).unwrap(), &context);
+
+        // 1d2! with up to two explosions: 1, 2+1, 2+2+1, 2+2+2
+        let expected = [(1, 0.5), (3, 0.25), (5, 0.125), (6, 0.125)];
+        let approx_eq = |dist: Distribution, expected: &[(i64, f64)]| {
+            dist.iter().len() == expected.len()
+                && dist
+                    .iter()
+                    .zip(expected)
+                    .all(|((v, p), &(ev, ep))| v == ev && (p - ep).abs() < 1e-12)
+        };
+        assert!(approx_eq(analyze("1d2!").unwrap(), &expected));
+        assert!(approx_eq(analyze("1d2!!").unwrap(), &expected));
+        // penetrating: 1, 2+0, 2+1+0, 2+1+1
+        let expected = [(1, 0.5), (2, 0.25), (3, 0.125), (4, 0.125)];
+        assert!(approx_eq(analyze("1d2!p").unwrap(), &expected));
+        // successes count each exploded die, or the total for compounding explosions
+        let expected = [(0, 0.5), (1, 0.25), (2, 0.125), (3, 0.125)];
+        assert!(approx_eq(analyze("1d2!=2=2").unwrap(), &expected));
+        let expected = [(0, 0.5), (1, 0.5)];
+        assert!(approx_eq(analyze("1d2!!=2>=3").unwrap(), &expected));
+    }
+
+    #[test]
+    fn test_errors() {
+        let cases = [
+            ("1d6 / (1d2 - 1)", Err(EvalErrorKind::DivisionByZero)),
+            (
+                "4d6kh5",
+                Err(EvalErrorKind::KeepTooMany {
+                    available: 4,
+                    requested: 5,
+                }),
+            ),
+            (
+                "(1d2 - 2)d6",
+                Err(EvalErrorKind::NegativeDiceCount { count: -1 }),
+            ),
+            ("1d6r<7", Err(EvalErrorKind::UnsatisfiableReroll)),
+            (
+                "1d100r<100",
+                Err(EvalErrorKind::RerollLimitExceeded { limit: 100 }),
+            ),
+            (
+                "nope(1)",
+                Err(EvalErrorKind::UnknownFunction {
+                    name: "nope".into(),
+                }),
+            ),
+            ("1d6r1", Ok(())),
+        ];
+        for (input, expected) in cases {
+            let result = analyze(input).map(|_| ());
+            assert_eq!(
+                result,
+                expected.map_err(AnalysisErrorKind::Eval),
+                "Input: {}",
+                input
+            );
+        }
+
+        let unsupported = [
+            "{2d6} + 1d6",
+            "{2d6} + {2d6}",
+            "{2d6}kh1 + 1",
+            "(2d6!)kh1",
+            "1d(2d6)",
+            "sort(2d6)",
+            "if 1d2 == 1 then 1 else {1d2}",
+        ];
+        for input in unsupported {
+            assert!(
+                matches!(analyze(input), Err(AnalysisErrorKind::Unsupported { .. })),
+                "Input: {}",
+                input
+            );
+        }
+        assert_eq!(analyze("1d1000000000"), Err(AnalysisErrorKind::TooComplex));
+    }
+
+    #[test]
+    fn test_matches_sampling() {
+        let inputs = [
+            "4d6kh3",
+            "2d20kh1 + 5 >= 15",
+            "3d6!!dl1 - 1d4",
+            "5d10>=8f1",
+            "if 1d20 >= 11 then 2d6 else 1d4 + 1",
+        ];
+        for input in inputs {
+            let expr = parse(input).unwrap();
+            let dist = distribution(&expr).unwrap();
+            let mut rng = StdRng::seed_from_u64(0);
+            let samples = 20_000;
+            let mean: f64 = dist.iter().map(|(v, p)| v as f64 * p).sum();
+            let sample_mean = (0..samples)
+                .map(|_| eval_with(&expr, &mut rng).unwrap().sum() as f64)
+                .sum::<f64>()
+                / samples as f64;
+            assert!((mean - sample_mean).abs() < 0.1, "Input: {}", input);
+        }
+    }
+}
@@ -0,0 +1,519 @@
+//! Discrete probability distributions over integers.
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+use super::AnalysisErrorKind;
+use crate::eval::{ArithmeticMode, EvalErrorKind};
+
+/// The maximum number of outcomes of a distribution.
+const MAX_OUTCOMES: usize = 1_000_000;
+/// The maximum number of pairs of outcomes combined by a single operation.
+const MAX_PAIRS: usize = 20_000_000;
+
+/// An exact discrete probability distribution over integers, i.e., the probability of each
+/// possible outcome of a random integer.
+///
+/// Distributions are produced by [`distribution`](super::distribution) and
+/// [`distribution_with_context`](super::distribution_with_context), or built from sampled results
+/// with [`from_samples`](Self::from_samples). Outcomes with a probability of zero are left out,
+/// and there's always at least one outcome.
+///
+/// # Example
+///
+/// ```
+/// # use rollkit::{analysis::distribution, parse};
+/// let dist = distribution(&parse("2d6").unwrap()).unwrap();
+/// assert_eq!(dist.iter().len(), 11);
+/// assert!((dist.probability(7) - 6.0 / 36.0).abs() < 1e-12);
+/// assert_eq!(dist.probability(13), 0.0);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution {
+    /// The outcomes and their probabilities, sorted by outcome.
+    outcomes: Vec<(i64, f64)>,
+}
+
+impl Distribution {
+    /// Creates a distribution with a single outcome of probability 1.
+    pub fn constant(value: i64) -> Self {
+        Self {
+            outcomes: vec![(value, 1.0)],
+        }
+    }
+
+    /// Creates a distribution from outcomes and their weights, in any order and possibly repeated.
+    /// The weights of repeated outcomes are added up. Weights aren't normalized.
+    ///
+    /// Fails if no outcome has a positive weight, e.g., as all the weights underflowed.
+    pub(super) fn from_weights(outcomes: Vec<(i64, f64)>) -> Result<Self, AnalysisErrorKind> {
+        let outcomes = merge_weights(outcomes);
+        if outcomes.is_empty() || outcomes.len() > MAX_OUTCOMES {
+            return Err(AnalysisErrorKind::TooComplex);
+        }
+        Ok(Self { outcomes })
+    }
+
+    /// Creates a uniform distribution over the faces of a die. Repeated faces are proportionally
+    /// more likely.
+    pub(super) fn uniform(faces: impl Iterator<Item = i64>) -> Result<Self, AnalysisErrorKind> {
+        let faces: Vec<i64> = faces.take(MAX_OUTCOMES + 1).collect();
+        if faces.len() > MAX_OUTCOMES {
+            return Err(AnalysisErrorKind::TooComplex);
+        }
+        let weight = 1.0 / faces.len() as f64;
+        Self::from_weights(faces.into_iter().map(|face| (face, weight)).collect())
+    }
+
+    /// Creates the empirical distribution of sampled outcomes, e.g., results of evaluating an
+    /// expression many times. Returns `None` if there are no samples.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rollkit::analysis::Distribution;
+    /// let dist = Distribution::from_samples([3, 1, 3, 3]).unwrap();
+    /// assert_eq!(dist.probability(3), 0.75);
+    /// assert_eq!(dist.mean(), 2.5);
+    /// assert!(Distribution::from_samples([]).is_none());
+    /// ```
+    pub fn from_samples(samples: impl IntoIterator<Item = i64>) -> Option<Self> {
+        let mut outcomes: Vec<(i64, f64)> = samples.into_iter().map(|value| (value, 1.0)).collect();
+        if outcomes.is_empty() {
+            return None;
+        }
+        let weight = 1.0 / outcomes.len() as f64;
+        outcomes.sort_unstable_by_key(|&(value, _)| value);
+        outcomes.dedup_by(|(value, _), (prev_value, prev_count)| {
+            if value == prev_value {
+                *prev_count += 1.0;
+                true
+            } else {
+                false
+            }
+        });
+        Some(Self {
+            outcomes: outcomes
+                .into_iter()
+                .map(|(value, count)| (value, count * weight))
+                .collect(),
+        })
+    }
+
+    /// Creates the empirical distribution of outcomes counted in a histogram sorted by outcome.
+    /// Returns `None` if nothing was counted.
+    pub(super) fn from_counts(histogram: &[(i64, usize)]) -> Option<Self> {
+        let total: usize = histogram.iter().map(|&(_, count)| count).sum();
+        if total == 0 {
+            return None;
+        }
+        let weight = 1.0 / total as f64;
+        Some(Self {
+            outcomes: histogram
+                .iter()
+                .filter(|&&(_, count)| count > 0)
+                .map(|&(value, count)| (value, count as f64 * weight))
+                .collect(),
+        })
+    }
+
+    /// Returns the probability of an outcome.
+    pub fn probability(&self, value: i64) -> f64 {
+        self.outcomes
+            .binary_search_by_key(&value, |&(value, _)| value)
+            .map_or(0.0, |index| self.outcomes[index].1)
+    }
+
+    /// Iterates over the outcomes and their probabilities, in ascending order of outcomes.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (i64, f64)> + DoubleEndedIterator + '_ {
+        self.outcomes.iter().copied()
+    }
+
+    /// Returns the probability of an outcome of at least `value`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rollkit::{analysis::distribution, parse};
+    /// let dist = distribution(&parse("1d20 + 5").unwrap()).unwrap();
+    /// assert!((dist.probability_at_least(15) - 0.55).abs() < 1e-12);
+    /// ```
+    pub fn probability_at_least(&self, value: i64) -> f64 {
+        let start = self
+            .outcomes
+            .partition_point(|&(outcome, _)| outcome < value);
+        self.outcomes[start..].iter().map(|(_, p)| p).sum()
+    }
+
+    /// Returns the lowest possible outcome.
+    pub fn min(&self) -> i64 {
+        self.outcomes[0].0
+    }
+
+    /// Returns the highest possible outcome.
+    pub fn max(&self) -> i64 {
+        self.outcomes[self.outcomes.len() - 1].0
+    }
+
+    /// Returns the mean, i.e., the expected value.
+    pub fn mean(&self) -> f64 {
+        self.iter().map(|(value, p)| value as f64 * p).sum()
+    }
+
+    /// Returns the variance.
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+        self.iter()
+            .map(|(value, p)| (value as f64 - mean) * (value as f64 - mean) * p)
+            .sum()
+    }
+
+    /// Returns the standard deviation.
+    pub fn std_dev(&self) -> f64 {
+        sqrt(self.variance())
+    }
+
+    /// Returns the median, i.e., the [50th percentile](Self::percentile).
+    pub fn median(&self) -> i64 {
+        self.percentile(50.0)
+    }
+
+    /// Returns the `percentile`-th percentile, i.e., the lowest outcome such that the outcome is
+    /// at most that with a probability of at least `percentile` percent.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `percentile` is not between 0 and 100.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rollkit::{analysis::distribution, parse};
+    /// let dist = distribution(&parse("1d20").unwrap()).unwrap();
+    /// assert_eq!(dist.percentile(0.0), 1);
+    /// assert_eq!(dist.percentile(25.0), 5);
+    /// assert_eq!(dist.median(), 10);
+    /// assert_eq!(dist.percentile(100.0), 20);
+    /// ```
+    pub fn percentile(&self, percentile: f64) -> i64 {
+        assert!(
+            (0.0..=100.0).contains(&percentile),
+            "Percentile must be between 0 and 100, got {}",
+            percentile
+        );
+
+        // tolerate rounding errors in the cumulative probabilities
+        let target = percentile / 100.0 - 1e-9;
+        let mut cumulative = 0.0;
+        for (value, p) in self.iter() {
+            cumulative += p;
+            if cumulative >= target {
+                return value;
+            }
+        }
+        self.max()
+    }
+
+    /// Returns the only outcome, if there's exactly one.
+    pub(super) fn as_constant(&self) -> Option<i64> {
+        match self.outcomes[..] {
+            [(value, _)] => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the total probability of the outcomes matching `pred`.
+    pub(super) fn mass(&self, pred: impl Fn(i64) -> bool) -> f64 {
+        self.iter()
+            .filter(|&(value, _)| pred(value))
+            .map(|(_, p)| p)
+            .sum()
+    }
+
+    /// Keeps only the outcomes matching `pred`, without normalizing. Returns `None` if no outcome
+    /// matches.
+    pub(super) fn filter(&self, pred: impl Fn(i64) -> bool) -> Option<Self> {
+        let outcomes: Vec<_> = self.iter().filter(|&(value, _)| pred(value)).collect();
+        (!outcomes.is_empty()).then_some(Self { outcomes })
+    }
+
+    /// Multiplies all probabilities by `factor`.
+    pub(super) fn scale(mut self, factor: f64) -> Self {
+        for (_, p) in &mut self.outcomes {
+            *p *= factor;
+        }
+        self
+    }
+
+    /// Applies `f` to each outcome.
+    pub(super) fn map(
+        &self,
+        f: impl Fn(i64) -> Result<i64, EvalErrorKind>,
+    ) -> Result<Self, AnalysisErrorKind> {
+        let outcomes = self
+            .iter()
+            .map(|(value, p)| Ok((f(value)?, p)))
+            .collect::<Result<_, EvalErrorKind>>()?;
+        Self::from_weights(outcomes)
+    }
+
+    /// Combines the outcomes of two independent distributions with `f`.
+    pub(super) fn combine(
+        &self,
+        other: &Self,
+        f: impl Fn(i64, i64) -> Result<i64, EvalErrorKind>,
+    ) -> Result<Self, AnalysisErrorKind> {
+        if self.outcomes.len().saturating_mul(other.outcomes.len()) > MAX_PAIRS {
+            return Err(AnalysisErrorKind::TooComplex);
+        }
+
+        let mut outcomes = Vec::with_capacity(self.outcomes.len() * other.outcomes.len());
+        for (l, lp) in self.iter() {
+            for (r, rp) in other.iter() {
+                outcomes.push((f(l, r)?, lp * rp));
+            }
+        }
+        Self::from_weights(outcomes)
+    }
+
+    /// Mixes distributions, each weighted by a probability.
+    pub(super) fn mixture(
+        parts: impl IntoIterator<Item = (f64, Self)>,
+    ) -> Result<Self, AnalysisErrorKind> {
+        let outcomes = parts
+            .into_iter()
+            .flat_map(|(weight, dist)| dist.outcomes.into_iter().map(move |(v, p)| (v, p * weight)))
+            .collect();
+        Self::from_weights(outcomes)
+    }
+
+    /// Gets the distribution of the sum of `count` independent outcomes, by repeated squaring.
+    pub(super) fn sum_of(
+        &self,
+        count: usize,
+        arith: ArithmeticMode,
+    ) -> Result<Self, AnalysisErrorKind> {
+        let add = |l, r| arith.add(l, r);
+        let mut result = Self::constant(0);
+        let mut power = self.clone();
+        let mut count = count;
+        while count > 0 {
+            if count & 1 == 1 {
+                result = result.combine(&power, add)?;
+            }
+            count >>= 1;
+            if count > 0 {
+                power = power.combine(&power, add)?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Gets the distribution of the sum of the `keep` highest, or lowest if `highest` is `false`,
+    /// of `count` independent outcomes.
+    ///
+    /// The outcomes are assigned to the dice from the most extreme outcome on. Once `keep` dice
+    /// are assigned, the rest of the dice can only get less extreme outcomes, which don't affect
+    /// the sum, so only the states with fewer dice assigned are tracked. The number of dice getting
+    /// each outcome is drawn from the probability of the outcome among the outcomes not assigned
+    /// yet, so the probabilities of the states stay representable for large pools.
+    pub(super) fn sum_of_extremes(
+        &self,
+        count: usize,
+        keep: usize,
+        highest: bool,
+        arith: ArithmeticMode,
+    ) -> Result<Self, AnalysisErrorKind> {
+        if keep == 0 {
+            return Ok(Self::constant(0));
+        }
+
+        let mut outcomes = self.outcomes.clone();
+        if highest {
+            outcomes.reverse();
+        }
+        // the probability of each outcome and the outcomes less extreme than it
+        let mut remaining: Vec<f64> = outcomes
+            .iter()
+            .rev()
+            .scan(0.0, |acc, &(_, p)| {
+                *acc += p;
+                Some(*acc)
+            })
+            .collect();
+        remaining.reverse();
+
+        // the probabilities of the sums of the kept outcomes, by the number of dice assigned so far
+        let mut states: Vec<Vec<(i64, f64)>> = vec![Vec::new(); keep];
+        states[0].push((0, 1.0));
+        let mut result = Vec::new();
+
+        for (&(value, p), &remaining) in outcomes.iter().zip(&remaining) {
+            let chance = (p / remaining).min(1.0);
+            let mut next: Vec<Vec<(i64, f64)>> = vec![Vec::new(); keep];
+            for (assigned, sums) in states.iter().enumerate() {
+                if sums.is_empty() {
+                    continue;
+                }
+                let counts = binomial(count - assigned, chance, keep - assigned);
+                for &(sum, weight) in sums {
+                    let mut sum = sum;
+                    for (t, &q) in counts.iter().enumerate() {
+                        if t > 0 {
+                            sum = arith.add(sum, value)?;
+                        }
+                        if assigned + t >= keep {
+                            result.push((sum, weight * q));
+                        } else {
+                            next[assigned + t].push((sum, weight * q));
+                        }
+                    }
+                }
+            }
+
+            states = next.into_iter().map(merge_weights).collect();
+            if states
+                .iter()
+                .map(Vec::len)
+                .sum::<usize>()
+                .saturating_mul(count)
+                > MAX_PAIRS
+            {
+                return Err(AnalysisErrorKind::TooComplex);
+            }
+        }
+
+        let dist = Self::from_weights(result)?;
+        if (dist.iter().map(|(_, p)| p).sum::<f64>() - 1.0).abs() > 1e-6 {
+            return Err(AnalysisErrorKind::TooComplex);
+        }
+        Ok(dist)
+    }
+}
+
+/// Sorts outcomes and their weights by outcome, adding up the weights of repeated outcomes and
+/// leaving out the outcomes without a positive weight.
+fn merge_weights(mut outcomes: Vec<(i64, f64)>) -> Vec<(i64, f64)> {
+    outcomes.sort_unstable_by_key(|&(value, _)| value);
+    outcomes.dedup_by(|(value, weight), (prev_value, prev_weight)| {
+        if value == prev_value {
+            *prev_weight += *weight;
+            true
+        } else {
+            false
+        }
+    });
+    outcomes.retain(|&(_, weight)| weight > 0.0);
+    outcomes
+}
+
+/// Gets the probabilities of `0..limit` of `n` independent events with probability `p` happening,
+/// followed by the probability of at least `limit` happening, if `limit` is at most `n`. Trailing
+/// zero probabilities are left out.
+///
+/// The probabilities are computed relative to the most likely count and then normalized, as
+/// the probabilities of counts far from it can underflow.
+fn binomial(n: usize, p: f64, limit: usize) -> Vec<f64> {
+    let mut weights = vec![0.0; n.min(limit) + 1];
+    let mode = (((n + 1) as f64 * p) as usize).min(n);
+    let odds = p / (1.0 - p);
+
+    let mut weight = 1.0;
+    weights[mode.min(limit)] += weight;
+    for t in mode..n {
+        weight *= (n - t) as f64 / (t + 1) as f64 * odds;
+        if weight == 0.0 {
+            break;
+        }
+        weights[(t + 1).min(limit)] += weight;
+    }
+    let mut weight = 1.0;
+    for t in (1..=mode).rev() {
+        weight *= t as f64 / (n - t + 1) as f64 / odds;
+        if weight == 0.0 {
+            break;
+        }
+        weights[(t - 1).min(limit)] += weight;
+    }
+
+    let total: f64 = weights.iter().sum();
+    for weight in &mut weights {
+        *weight /= total;
+    }
+    while weights.last() == Some(&0.0) {
+        weights.pop();
+    }
+    weights
+}
+
+/// Computes the square root of a non-negative number with Newton's method, as `f64::sqrt` requires
+/// the standard library.
+pub(super) fn sqrt(value: f64) -> f64 {
+    if value <= 0.0 || !value.is_finite() {
+        return value.max(0.0);
+    }
+    let mut x = if value > 1.0 { value } else { 1.0 };
+    loop {
+        let next = (x + value / x) / 2.0;
+        if next >= x {
+            return x;
+        }
+        x = next;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{analysis::distribution, parse};
+
+    fn analyze(input: &str) -> Distribution {
+        distribution(&parse(input).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_stats() {
+        let dist = analyze("4d6kh3");
+        assert_eq!((dist.min(), dist.max()), (3, 18));
+        assert!((dist.mean() - 15869.0 / 1296.0).abs() < 1e-9);
+        assert!((dist.std_dev() - 2.8468).abs() < 1e-4);
+        assert_eq!(dist.median(), 12);
+        assert!((dist.probability_at_least(18) - 21.0 / 1296.0).abs() < 1e-12);
+        assert_eq!(
+            dist.probability_at_least(3),
+            dist.iter().map(|(_, p)| p).sum()
+        );
+        assert_eq!(dist.probability_at_least(19), 0.0);
+
+        let dist = analyze("2d6");
+        assert_eq!(dist.mean(), 7.0);
+        assert!((dist.variance() - 35.0 / 6.0).abs() < 1e-12);
+        assert_eq!(dist.percentile(100.0 / 36.0), 2);
+        assert_eq!(dist.percentile(100.0 * 3.0 / 36.0), 3);
+        assert_eq!(dist.percentile(100.0 * 4.0 / 36.0), 4);
+
+        let dist = analyze("5");
+        assert_eq!((dist.mean(), dist.std_dev()), (5.0, 0.0));
+        assert_eq!(dist.percentile(0.0), 5);
+
+        // distributions always have an outcome
+        assert_eq!(
+            Distribution::from_weights(vec![(1, 0.0), (2, 0.0)]),
+            Err(AnalysisErrorKind::TooComplex)
+        );
+    }
+
+    #[test]
+    fn test_sqrt() {
+        for value in [0.0, 1e-6, 0.25, 1.0, 2.0, 35.0 / 6.0, 1e12] {
+            let root = sqrt(value);
+            assert!(
+                (root * root - value).abs() <= value * 1e-12,
+                "sqrt({})",
+                value
+            );
+        }
+    }
+}
@@ -0,0 +1,402 @@
+//! Monte Carlo estimation of the distribution of expressions.
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+use alloc::{collections::BTreeMap, vec::Vec};
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+
+use rand::{SeedableRng, rngs::StdRng};
+
+use super::{Distribution, distribution::sqrt};
+use crate::{
+    ast::Expr,
+    eval::{EvalContext, EvalError, EvalPlan, Value},
+};
+
+/// The number of samples drawn from each random number stream. Samples are split into batches of
+/// this size regardless of the number of threads, so the results only depend on the seed.
+const BATCH_SIZE: usize = 4096;
+
+/// Options for [`sample`] and [`sample_with_context`].
+///
+/// # Example
+///
+/// ```
+/// # use rollkit::{analysis::{SampleOptions, sample}, parse};
+/// let options = SampleOptions { samples: 10_000, seed: 42, threads: 4 };
+/// let estimate = sample(&parse("3d6!").unwrap(), &options).unwrap();
+/// assert_eq!(estimate.samples(), 10_000);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleOptions {
+    /// The number of times the expression is evaluated. Defaults to 100,000.
+    pub samples: usize,
+    /// The seed of the random number generator. The same seed gives the same results, whatever the
+    /// number of threads. Defaults to 0.
+    pub seed: u64,
+    /// The number of threads to evaluate on. Only used with the `std` feature, otherwise all
+    /// samples are evaluated on the current thread. Defaults to 1.
+    pub threads: usize,
+}
+
+impl Default for SampleOptions {
+    fn default() -> Self {
+        Self {
+            samples: 100_000,
+            seed: 0,
+            threads: 1,
+        }
+    }
+}
+
+/// A confidence interval around an estimated quantity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConfidenceInterval {
+    /// The point estimate.
+    pub estimate: f64,
+    /// The lower bound of the interval.
+    pub low: f64,
+    /// The upper bound of the interval.
+    pub high: f64,
+}
+
+impl ConfidenceInterval {
+    /// Returns `true` if the interval contains `value`.
+    pub fn contains(&self, value: f64) -> bool {
+        self.low <= value && value <= self.high
+    }
+}
+
+/// The results of evaluating an expression many times, produced by [`sample`] and
+/// [`sample_with_context`].
+///
+/// Confidence intervals take the z-score of the confidence level, e.g., `1.96` for 95% or `2.576`
+/// for 99%.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    /// The number of times each result occurred, sorted by result.
+    histogram: Vec<(i64, usize)>,
+    samples: usize,
+}
+
+impl Estimate {
+    /// Returns the number of samples.
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// Returns the number of times each result occurred, sorted by result.
+    pub fn histogram(&self) -> &[(i64, usize)] {
+        &self.histogram
+    }
+
+    /// Returns the empirical distribution of the results, with the frequency of each result as its
+    /// probability. Returns `None` if there are no samples.
+    pub fn distribution(&self) -> Option<Distribution> {
+        Distribution::from_counts(&self.histogram)
+    }
+
+    /// Returns a confidence interval of the mean of the result, using the normal approximation.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rollkit::{analysis::{SampleOptions, sample}, parse};
+    /// let estimate = sample(&parse("2d6").unwrap(), &SampleOptions::default()).unwrap();
+    /// assert!(estimate.mean_interval(3.0).contains(7.0));
+    /// ```
+    pub fn mean_interval(&self, z: f64) -> ConfidenceInterval {
+        let Some(dist) = self.distribution() else {
+            return ConfidenceInterval {
+                estimate: 0.0,
+                low: f64::NEG_INFINITY,
+                high: f64::INFINITY,
+            };
+        };
+        let mean = dist.mean();
+        let margin = z * dist.std_dev() / sqrt(self.samples as f64);
+        ConfidenceInterval {
+            estimate: mean,
+            low: mean - margin,
+            high: mean + margin,
+        }
+    }
+
+    /// Returns a confidence interval of the probability of a result of at least `value`, using
+    /// the Wilson score interval.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rollkit::{analysis::{SampleOptions, sample}, parse};
+    /// let estimate = sample(&parse("1d20").unwrap(), &SampleOptions::default()).unwrap();
+    /// assert!(estimate.probability_at_least_interval(20, 3.0).contains(0.05));
+    /// ```
+    pub fn probability_at_least_interval(&self, value: i64, z: f64) -> ConfidenceInterval {
+        let start = self
+            .histogram
+            .partition_point(|&(result, _)| result < value);
+        let hits: usize = self.histogram[start..]
+            .iter()
+            .map(|&(_, count)| count)
+            .sum();
+        let n = self.samples as f64;
+        if self.samples == 0 {
+            return ConfidenceInterval {
+                estimate: 0.0,
+                low: 0.0,
+                high: 1.0,
+            };
+        }
+
+        let p = hits as f64 / n;
+        let z2 = z * z;
+        let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+        let margin = z / (1.0 + z2 / n) * sqrt(p * (1.0 - p) / n + z2 / (4.0 * n * n));
+        ConfidenceInterval {
+            estimate: p,
+            low: (center - margin).max(0.0),
+            high: (center + margin).min(1.0),
+        }
+    }
+}
+
+/// The results of evaluating a batch of samples.
+type BatchResult = Result<BTreeMap<i64, usize>, EvalError>;
+
+/// Evaluates the samples of a batch with `eval` and the batch's own random number stream, which is
+/// seeded with both the seed and the index of the batch.
+fn sample_batch(
+    eval: &impl Fn(&mut StdRng) -> Result<i64, EvalError>,
+    options: &SampleOptions,
+    batch: usize,
+) -> BatchResult {
+    let mut seed = [0; 32];
+    seed[..8].copy_from_slice(&options.seed.to_le_bytes());
+    seed[8..16].copy_from_slice(&(batch as u64).to_le_bytes());
+    let mut rng = StdRng::from_seed(seed);
+
+    let len = BATCH_SIZE.min(options.samples - batch * BATCH_SIZE);
+    let mut counts = BTreeMap::new();
+    for _ in 0..len {
+        let result = eval(&mut rng)?;
+        *counts.entry(result).or_insert(0) += 1;
+    }
+    Ok(counts)
+}
+
+/// Evaluates the batches `first, first + step, ...`, stopping at the first error. Returns the
+/// merged counts, or the index of the failed batch and its error.
+fn sample_batches(
+    eval: &impl Fn(&mut StdRng) -> Result<i64, EvalError>,
+    options: &SampleOptions,
+    first: usize,
+    step: usize,
+) -> Result<BTreeMap<i64, usize>, (usize, EvalError)> {
+    let batches = options.samples.div_ceil(BATCH_SIZE);
+    let mut counts = BTreeMap::new();
+    for batch in (first..batches).step_by(step) {
+        let batch_counts = sample_batch(eval, options, batch).map_err(|error| (batch, error))?;
+        for (result, count) in batch_counts {
+            *counts.entry(result).or_insert(0) += count;
+        }
+    }
+    Ok(counts)
+}
+
+/// Estimates the distribution of the result of a RollKit expression by evaluating it many times,
+/// with the default [evaluation context](EvalContext). See [`sample_with_context`] for details.
+///
+/// # Examples
+///
+/// ```
+/// # use rollkit::{analysis::{SampleOptions, sample}, parse};
+/// let expr = parse("4d6kh3").unwrap();
+/// let estimate = sample(&expr, &SampleOptions::default()).unwrap();
+/// let mean = estimate.mean_interval(1.96);
+/// println!("mean: {:.3} ({:.3} - {:.3})", mean.estimate, mean.low, mean.high);
+/// ```
+pub fn sample(expr: &Expr, options: &SampleOptions) -> Result<Estimate, EvalError> {
+    sample_with_context(expr, options, &EvalContext::default())
+}
+
+/// Estimates the distribution of the result of a RollKit expression by evaluating it many times
+/// like [`eval_with_context`](crate::eval_with_context) does, where exact
+/// [analysis](super::distribution_with_context) is infeasible, e.g., for user-defined functions.
+/// Lists are summed up.
+///
+/// The expression is compiled into an [`EvalPlan`](crate::EvalPlan) once. The samples are split
+/// into fixed-size batches, each evaluated with its own random number generator seeded with the
+/// seed of the options and the index of the batch. Batches are spread over the threads, and the
+/// results only depend on the seed, not on the number of threads.
+///
+/// If any evaluation fails, the error of the first failing batch is returned.
+///
+/// # Examples
+///
+/// ```
+/// # use rollkit::{EvalContext, analysis::{SampleOptions, sample_with_context}, parse};
+/// let expr = parse("1d6!").unwrap();
+/// let context = EvalContext { explode_limit: 1, ..Default::default() };
+/// let options = SampleOptions { samples: 1_000, ..Default::default() };
+/// let estimate = sample_with_context(&expr, &options, &context).unwrap();
+/// assert!(estimate.histogram().iter().all(|&(result, _)| (1..=12).contains(&result)));
+///
+/// let parallel = SampleOptions { threads: 3, ..options };
+/// assert_eq!(sample_with_context(&expr, &parallel, &context).unwrap(), estimate);
+/// ```
+pub fn sample_with_context(
+    expr: &Expr,
+    options: &SampleOptions,
+    context: &EvalContext,
+) -> Result<Estimate, EvalError> {
+    let plan = EvalPlan::compile(expr, context);
+    // the result is the sum of the value, which fails on overflow like in analysis
+    let eval = &|rng: &mut StdRng| match plan.eval(rng)? {
+        Value::Integer(result) => Ok(result),
+        Value::List(values) => context
+            .arithmetic
+            .sum(values)
+            .map_err(|kind| EvalError::new(kind, Some(expr.span))),
+    };
+
+    #[cfg(feature = "std")]
+    let results: Vec<_> = {
+        let batches = options.samples.div_ceil(BATCH_SIZE);
+        let threads = options.threads.clamp(1, batches.max(1));
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads)
+                .map(|first| scope.spawn(move || sample_batches(eval, options, first, threads)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    };
+    #[cfg(not(feature = "std"))]
+    let results = [sample_batches(eval, options, 0, 1)];
+
+    let mut counts = BTreeMap::new();
+    let mut failed: Option<(usize, EvalError)> = None;
+    for result in results {
+        match result {
+            Ok(thread_counts) => {
+                for (result, count) in thread_counts {
+                    *counts.entry(result).or_insert(0) += count;
+                }
+            }
+            Err((batch, error)) => {
+                if failed.as_ref().is_none_or(|(first, _)| batch < *first) {
+                    failed = Some((batch, error));
+                }
+            }
+        }
+    }
+
+    match failed {
+        Some((_, error)) => Err(error),
+        None => Ok(Estimate {
+            histogram: counts.into_iter().collect(),
+            samples: options.samples,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{EvalErrorKind, FunctionRegistry, Value, analysis::distribution, parse};
+
+    #[test]
+    fn test_deterministic() {
+        let expr = parse("3d6!r1 + 1d4").unwrap();
+        let options = SampleOptions {
+            samples: 20_000,
+            seed: 7,
+            threads: 1,
+        };
+        let estimate = sample(&expr, &options).unwrap();
+        assert_eq!(estimate.samples(), 20_000);
+        assert_eq!(
+            estimate.histogram().iter().map(|&(_, c)| c).sum::<usize>(),
+            20_000
+        );
+        for threads in [2, 3, 16] {
+            let options = SampleOptions { threads, ..options };
+            assert_eq!(sample(&expr, &options).unwrap(), estimate);
+        }
+
+        let options = SampleOptions { seed: 8, ..options };
+        assert_ne!(sample(&expr, &options).unwrap(), estimate);
+    }
+
+    #[test]
+    fn test_matches_analysis() {
+        let options = SampleOptions {
+            samples: 50_000,
+            threads: 4,
+            ..Default::default()
+        };
+        for input in ["4d6kh3", "2d20kh1 + 5 >= 15", "5d10>=8f1"] {
+            let expr = parse(input).unwrap();
+            let exact = distribution(&expr).unwrap();
+            let estimate = sample(&expr, &options).unwrap();
+            assert!(
+                estimate.mean_interval(4.0).contains(exact.mean()),
+                "Input: {}",
+                input
+            );
+            let median = exact.median();
+            assert!(
+                estimate
+                    .probability_at_least_interval(median, 4.0)
+                    .contains(exact.probability_at_least(median)),
+                "Input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_errors_and_functions() {
+        let expr = parse("1d6 / (1d2 - 1)").unwrap();
+        let error = sample(&expr, &SampleOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), &EvalErrorKind::DivisionByZero);
+
+        // the sum of the result overflows
+        let expr = parse("{9223372036854775807, 1}").unwrap();
+        let error = sample(&expr, &SampleOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), &EvalErrorKind::Overflow { operation: "sum" });
+
+        let mut functions = FunctionRegistry::new();
+        functions.register("double", |args| Ok(Value::Integer(args[0].sum() * 2)));
+        let context = EvalContext {
+            functions,
+            ..Default::default()
+        };
+        let options = SampleOptions {
+            samples: 1_000,
+            threads: 2,
+            ..Default::default()
+        };
+        let expr = parse("double(1d2)").unwrap();
+        let estimate = sample_with_context(&expr, &options, &context).unwrap();
+        assert_eq!(estimate.histogram().len(), 2);
+        assert!(
+            estimate
+                .histogram()
+                .iter()
+                .all(|&(result, _)| result == 2 || result == 4)
+        );
+
+        let empty = SampleOptions {
+            samples: 0,
+            ..Default::default()
+        };
+        let estimate = sample(&expr, &empty).unwrap();
+        assert!(estimate.distribution().is_none());
+    }
+}
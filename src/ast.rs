@@ -8,7 +8,7 @@ use alloc::{
     vec::Vec,
 };
 
-use core::{fmt, iter};
+use core::{fmt, iter, ops::Range};
 
 /// A range literal in the RollKit expression AST.
 ///
@@ -104,8 +104,18 @@ pub enum BinaryOperator {
     DropLowest,
 
     // Arithmetic operators
+    /// The exponentiation operator `^`, also written as `**`.
+    Exponentiation,
     /// The multiplication operator `*`.
     Multiplication,
+    /// The division operator `/`, rounding toward zero.
+    Division,
+    /// The floor division operator `//`, rounding toward negative infinity.
+    FloorDivision,
+    /// The ceiling division operator `/^`, rounding toward positive infinity.
+    CeilDivision,
+    /// The modulo operator `%`, with the sign of the result following the left operand.
+    Modulo,
     /// The addition operator `+`.
     Addition,
     /// The subtraction operator `-`.
@@ -135,7 +145,12 @@ impl BinaryOperator {
             | BinaryOperator::KeepLowest
             | BinaryOperator::DropHighest
             | BinaryOperator::DropLowest => 130,
-            BinaryOperator::Multiplication => 90,
+            BinaryOperator::Exponentiation => 110,
+            BinaryOperator::Multiplication
+            | BinaryOperator::Division
+            | BinaryOperator::FloorDivision
+            | BinaryOperator::CeilDivision
+            | BinaryOperator::Modulo => 90,
             BinaryOperator::Addition | BinaryOperator::Subtraction => 70,
             BinaryOperator::Equal
             | BinaryOperator::NotEqual
@@ -154,7 +169,12 @@ impl BinaryOperator {
             BinaryOperator::KeepLowest => "kl",
             BinaryOperator::DropHighest => "dh",
             BinaryOperator::DropLowest => "dl",
+            BinaryOperator::Exponentiation => "^",
             BinaryOperator::Multiplication => "*",
+            BinaryOperator::Division => "/",
+            BinaryOperator::FloorDivision => "//",
+            BinaryOperator::CeilDivision => "/^",
+            BinaryOperator::Modulo => "%",
             BinaryOperator::Addition => "+",
             BinaryOperator::Subtraction => "-",
             BinaryOperator::Equal => "==",
@@ -174,7 +194,12 @@ impl BinaryOperator {
             BinaryOperator::KeepLowest => "Keep Lowest",
             BinaryOperator::DropHighest => "Drop Highest",
             BinaryOperator::DropLowest => "Drop Lowest",
+            BinaryOperator::Exponentiation => "Exponentiation",
             BinaryOperator::Multiplication => "Multiplication",
+            BinaryOperator::Division => "Division",
+            BinaryOperator::FloorDivision => "Floor Division",
+            BinaryOperator::CeilDivision => "Ceiling Division",
+            BinaryOperator::Modulo => "Modulo",
             BinaryOperator::Addition => "Addition",
             BinaryOperator::Subtraction => "Subtraction",
             BinaryOperator::Equal => "Equal",
@@ -193,15 +218,273 @@ impl fmt::Display for BinaryOperator {
     }
 }
 
+/// A unary operator in the RollKit expression AST.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UnaryOperator {
+    /// The negation operator `-`.
+    Negation,
+    /// The unary plus operator `+`.
+    Plus,
+}
+
+impl UnaryOperator {
+    /// Returns the precedence of the operator.
+    pub fn precedence(&self) -> u16 {
+        match self {
+            UnaryOperator::Negation | UnaryOperator::Plus => 100,
+        }
+    }
+
+    /// Returns the string representation of the operator.
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            UnaryOperator::Negation => "-",
+            UnaryOperator::Plus => "+",
+        }
+    }
+
+    /// Returns a description of the operator.
+    pub fn desc(&self) -> &'static str {
+        match self {
+            UnaryOperator::Negation => "Negation",
+            UnaryOperator::Plus => "Unary Plus",
+        }
+    }
+}
+
+impl fmt::Display for UnaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_str())
+    }
+}
+
+/// A comparison operator used in [compare points](ComparePoint).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CompareOp {
+    /// Equal to, `=`.
+    Equal,
+    /// Less than, `<`.
+    LessThan,
+    /// Less than or equal to, `<=`.
+    LessEqual,
+    /// Greater than, `>`.
+    GreaterThan,
+    /// Greater than or equal to, `>=`.
+    GreaterEqual,
+}
+
+impl CompareOp {
+    /// Returns the string representation of the operator.
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            CompareOp::Equal => "=",
+            CompareOp::LessThan => "<",
+            CompareOp::LessEqual => "<=",
+            CompareOp::GreaterThan => ">",
+            CompareOp::GreaterEqual => ">=",
+        }
+    }
+}
+
+impl fmt::Display for CompareOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_str())
+    }
+}
+
+/// A compare point, selecting die faces by comparing them with a value, e.g., the `>=5` in
+/// `3d6!>=5`.
+///
+/// # Example
+///
+/// ```
+/// # use rollkit::parsing::{CompareOp, ComparePoint};
+/// let point = ComparePoint { op: CompareOp::GreaterEqual, value: 5 };
+/// assert!(point.matches(6));
+/// assert!(!point.matches(4));
+/// assert_eq!(point.to_string(), ">=5");
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ComparePoint {
+    /// The comparison operator.
+    pub op: CompareOp,
+    /// The value to compare with.
+    pub value: i64,
+}
+
+impl ComparePoint {
+    /// Returns `true` if the face matches this compare point.
+    pub fn matches(&self, face: i64) -> bool {
+        match self.op {
+            CompareOp::Equal => face == self.value,
+            CompareOp::LessThan => face < self.value,
+            CompareOp::LessEqual => face <= self.value,
+            CompareOp::GreaterThan => face > self.value,
+            CompareOp::GreaterEqual => face >= self.value,
+        }
+    }
+}
+
+impl fmt::Display for ComparePoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.op, self.value)
+    }
+}
+
+/// The kind of an [explode](DiceModifier::Explode) modifier, deciding what happens to the extra
+/// rolls of an exploding die.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExplodeKind {
+    /// Plain explosion `!`, each extra roll is added to the list as a new die.
+    Standard,
+    /// Compounding explosion `!!`, extra rolls are summed into the exploding die, so the number of
+    /// dice in the list stays the same.
+    Compounding,
+    /// Penetrating explosion `!p`, each extra roll minus 1 is added to the list as a new die.
+    Penetrating,
+}
+
+impl ExplodeKind {
+    /// Returns the string representation of the explode kind.
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            ExplodeKind::Standard => "!",
+            ExplodeKind::Compounding => "!!",
+            ExplodeKind::Penetrating => "!p",
+        }
+    }
+}
+
+/// A modifier changing how the dice of a dice roll are rolled, e.g., the `!` in `3d6!`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DiceModifier {
+    /// The explode modifiers `!`, `!!` and `!p`. Each die matching the condition is rolled again,
+    /// and the extra roll can explode again. The [kind](ExplodeKind) decides how the extra rolls
+    /// are added to the result. Without a condition, dice explode on the highest face.
+    Explode {
+        /// The kind of the explosion.
+        kind: ExplodeKind,
+        /// The condition on which dice explode. If `None`, dice explode on the highest face.
+        condition: Option<ComparePoint>,
+    },
+    /// The reroll modifiers `r` and `ro`. Each roll matching the condition is rerolled, either
+    /// until it no longer matches (`r`), or at most once (`ro`).
+    Reroll {
+        /// If `true`, each die is rerolled at most once.
+        once: bool,
+        /// The condition on which dice are rerolled.
+        condition: ComparePoint,
+    },
+    /// The success counting modifier, e.g., `>=8` or `>=5f1`. Instead of a list of dice, the dice
+    /// roll results in the number of dice matching the success condition, minus the number of dice
+    /// matching the failure condition.
+    CountSuccesses {
+        /// The condition for a die to count as a success.
+        success: ComparePoint,
+        /// The condition for a die to count as a failure, if any.
+        failure: Option<ComparePoint>,
+    },
+}
+
+impl DiceModifier {
+    /// Returns a description of the modifier.
+    pub fn desc(&self) -> &'static str {
+        match self {
+            DiceModifier::Explode { kind, .. } => match kind {
+                ExplodeKind::Standard => "Explode",
+                ExplodeKind::Compounding => "Compounding Explode",
+                ExplodeKind::Penetrating => "Penetrating Explode",
+            },
+            DiceModifier::Reroll { once: false, .. } => "Reroll",
+            DiceModifier::Reroll { once: true, .. } => "Reroll Once",
+            DiceModifier::CountSuccesses { .. } => "Count Successes",
+        }
+    }
+}
+
+impl fmt::Display for DiceModifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiceModifier::Explode { kind, condition } => {
+                write!(f, "{}", kind.to_str())?;
+                if let Some(condition) = condition {
+                    write!(f, "{}", condition)?;
+                }
+                Ok(())
+            }
+            DiceModifier::Reroll { once, condition } => {
+                write!(f, "{}{}", if *once { "ro" } else { "r" }, condition)
+            }
+            DiceModifier::CountSuccesses { success, failure } => {
+                write!(f, "{}", success)?;
+                if let Some(failure) = failure {
+                    write!(f, "f{}", failure)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A span in the source text of an expression, as a range of byte offsets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// The start of the span, inclusive.
+    pub start: usize,
+    /// The end of the span, exclusive.
+    pub end: usize,
+}
+
+impl Span {
+    /// Creates a span from its start and end.
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Converts the span into a range, e.g., to slice the source text.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rollkit::parse;
+    /// let input = "1 + 4d6kh3";
+    /// let expr = parse(input).unwrap();
+    /// assert_eq!(&input[expr.span.into_range()], "1 + 4d6kh3");
+    /// ```
+    pub fn into_range(self) -> Range<usize> {
+        self.start..self.end
+    }
+}
+
+impl From<Range<usize>> for Span {
+    fn from(range: Range<usize>) -> Self {
+        Self::new(range.start, range.end)
+    }
+}
+
+impl From<Span> for Range<usize> {
+    fn from(span: Span) -> Self {
+        span.into_range()
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
 /// A RollKit expression.
 ///
-/// It's an AST node representing one of the possible expressions types in RollKit: literals,
-/// binary operations, function calls, and strong lists.
+/// It's an AST node representing one of the possible expressions types in RollKit, given by its
+/// [kind](ExprKind), along with its [span](Span) in the source text.
 ///
 /// # Creation
 ///
 /// [`Expr`]s are typically created by [parsing](crate::parse) RollKit expressions from strings.
-/// They can (naturally) also be constructed manually.
+/// They can (naturally) also be constructed manually, from an [`ExprKind`] with
+/// [`Expr::new`], or without a meaningful span with [`From<ExprKind>`](#impl-From<ExprKind>-for-Expr).
+/// Spans are ignored when comparing expressions.
 ///
 /// # Usage
 ///
@@ -216,10 +499,27 @@ impl fmt::Display for BinaryOperator {
 /// let result = eval(&expr).unwrap();
 /// assert!(matches!(result, rollkit::Value::Integer(n) if n >= 5 && n <= 15));
 /// ```
+#[derive(Debug, Clone)]
+pub struct Expr {
+    /// The kind of the expression, with its operands.
+    pub kind: ExprKind,
+    /// The span of the expression in the source text.
+    pub span: Span,
+}
+
+/// The kinds of [RollKit expressions](Expr): literals, unary operations, binary operations, dice
+/// rolls with modifiers, function calls, strong lists, variables, let-bindings and conditionals.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Expr {
+pub enum ExprKind {
     /// The expression is a literal value.
     Literal(Literal),
+    /// The expression is a unary operation.
+    UnaryOp {
+        /// The unary operator.
+        op: UnaryOperator,
+        /// The operand of the unary operation.
+        expr: Box<Expr>,
+    },
     /// The expression is a binary operation.
     BinaryOp {
         /// The left operand of the binary operation.
@@ -229,6 +529,16 @@ pub enum Expr {
         /// The right operand of the binary operation.
         right: Box<Expr>,
     },
+    /// The expression is a dice roll with modifiers, e.g., `3d6!`. Dice rolls without modifiers
+    /// are [binary operations](BinaryOperator::DiceRoll).
+    ModifiedDice {
+        /// The number of dice to roll.
+        count: Box<Expr>,
+        /// The sides of the dice, either an integer or a list of faces.
+        sides: Box<Expr>,
+        /// The modifiers, in the order they are written.
+        modifiers: Vec<DiceModifier>,
+    },
     /// The expression is a function call.
     FunctionCall {
         /// The name of the function.
@@ -238,9 +548,44 @@ pub enum Expr {
     },
     /// The expression is a strong list.
     StrongList(Box<Expr>),
+    /// The expression is a reference to a variable, e.g., `atk`, or a host variable, e.g.,
+    /// `@str_mod`.
+    Variable {
+        /// The name of the variable, without the `@` of host variables.
+        name: String,
+        /// Whether the variable is a host variable, i.e., one of the
+        /// [variables of the evaluation context](crate::EvalContext::variables) rather than one
+        /// bound by `let`.
+        host: bool,
+    },
+    /// The expression binds a variable in its body, e.g., `let atk = 1d20 + 5; atk >= 15`. The
+    /// value is evaluated once, before the body.
+    Let {
+        /// The name of the variable.
+        name: String,
+        /// The value bound to the variable.
+        value: Box<Expr>,
+        /// The expression in which the variable is bound.
+        body: Box<Expr>,
+    },
+    /// The expression is a conditional, e.g., `if 1d20 == 20 then 2d8 else 1d8`. Only the branch
+    /// taken is evaluated.
+    If {
+        /// The condition, which is true if it's non-zero.
+        condition: Box<Expr>,
+        /// The expression evaluated if the condition is true.
+        then_branch: Box<Expr>,
+        /// The expression evaluated if the condition is false.
+        else_branch: Box<Expr>,
+    },
 }
 
 impl Expr {
+    /// Creates an expression of the given kind and span.
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
     /// Formats this RollKit expression in a single line, with parentheses to indicate precedence.
     ///
     /// This is a wrapper around the [InlineFormatter](crate::parsing::InlineFormatter).
@@ -259,6 +604,22 @@ impl Expr {
     }
 }
 
+impl From<ExprKind> for Expr {
+    /// Creates an expression of the given kind, with an empty span.
+    fn from(kind: ExprKind) -> Self {
+        Self::new(kind, Span::default())
+    }
+}
+
+impl PartialEq for Expr {
+    /// Compares the expressions, ignoring their spans.
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl Eq for Expr {}
+
 /// Trait for visitors traversing RollKit expressions using the visitor pattern.
 ///
 /// # Example
@@ -275,21 +636,60 @@ pub trait ExprVisitor {
 
     /// Visits a literal.
     fn visit_literal(&mut self, literal: &Literal) -> Self::Output;
+    /// Visits a unary operation.
+    fn visit_unary_op(&mut self, op: &UnaryOperator, expr: &Expr) -> Self::Output;
     /// Visits a binary operation.
     fn visit_binary_op(&mut self, left: &Expr, op: &BinaryOperator, right: &Expr) -> Self::Output;
+    /// Visits a dice roll with modifiers.
+    fn visit_modified_dice(
+        &mut self,
+        count: &Expr,
+        sides: &Expr,
+        modifiers: &[DiceModifier],
+    ) -> Self::Output;
     /// Visits a function call.
     fn visit_function_call(&mut self, name: &str, args: &[Expr]) -> Self::Output;
     /// Visits a strong list.
     fn visit_strong_list(&mut self, expr: &Expr) -> Self::Output;
+    /// Visits a variable reference.
+    fn visit_variable(&mut self, name: &str, host: bool) -> Self::Output;
+    /// Visits a let-binding.
+    fn visit_let(&mut self, name: &str, value: &Expr, body: &Expr) -> Self::Output;
+    /// Visits a conditional.
+    fn visit_if(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> Self::Output;
 
-    /// Visits an expression.
+    /// Visits an expression. The default implementation dispatches to the other methods with
+    /// [`walk_expr`]; visitors overriding it can call [`walk_expr`] to do the same.
     fn visit_expr(&mut self, expr: &Expr) -> Self::Output {
-        match expr {
-            Expr::Literal(lit) => self.visit_literal(lit),
-            Expr::BinaryOp { left, op, right } => self.visit_binary_op(left, op, right),
-            Expr::FunctionCall { name, args } => self.visit_function_call(name, args),
-            Expr::StrongList(inner) => self.visit_strong_list(inner),
-        }
+        walk_expr(self, expr)
+    }
+}
+
+/// Dispatches an expression to the method of the visitor for its kind.
+pub fn walk_expr<V: ExprVisitor + ?Sized>(visitor: &mut V, expr: &Expr) -> V::Output {
+    match &expr.kind {
+        ExprKind::Literal(lit) => visitor.visit_literal(lit),
+        ExprKind::UnaryOp { op, expr } => visitor.visit_unary_op(op, expr),
+        ExprKind::BinaryOp { left, op, right } => visitor.visit_binary_op(left, op, right),
+        ExprKind::ModifiedDice {
+            count,
+            sides,
+            modifiers,
+        } => visitor.visit_modified_dice(count, sides, modifiers),
+        ExprKind::FunctionCall { name, args } => visitor.visit_function_call(name, args),
+        ExprKind::StrongList(inner) => visitor.visit_strong_list(inner),
+        ExprKind::Variable { name, host } => visitor.visit_variable(name, *host),
+        ExprKind::Let { name, value, body } => visitor.visit_let(name, value, body),
+        ExprKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => visitor.visit_if(condition, then_branch, else_branch),
     }
 }
 
@@ -330,12 +730,34 @@ impl ExprVisitor for InlineFormatter {
         }
     }
 
+    fn visit_unary_op(&mut self, op: &UnaryOperator, expr: &Expr) -> Self::Output {
+        format!("({}{})", op, self.visit_expr(expr))
+    }
+
     fn visit_binary_op(&mut self, left: &Expr, op: &BinaryOperator, right: &Expr) -> Self::Output {
         let left_str = self.visit_expr(left);
         let right_str = self.visit_expr(right);
         format!("({} {} {})", left_str, op, right_str)
     }
 
+    fn visit_modified_dice(
+        &mut self,
+        count: &Expr,
+        sides: &Expr,
+        modifiers: &[DiceModifier],
+    ) -> Self::Output {
+        let count_str = self.visit_expr(count);
+        let sides_str = self.visit_expr(sides);
+        let modifiers_str: String = modifiers.iter().map(|m| m.to_string()).collect();
+        format!(
+            "({} {} {}{})",
+            count_str,
+            BinaryOperator::DiceRoll,
+            sides_str,
+            modifiers_str
+        )
+    }
+
     fn visit_function_call(&mut self, name: &str, args: &[Expr]) -> Self::Output {
         let args_str = args
             .iter()
@@ -348,12 +770,36 @@ impl ExprVisitor for InlineFormatter {
     fn visit_strong_list(&mut self, expr: &Expr) -> Self::Output {
         format!("{{{}}}", self.visit_expr(expr))
     }
+
+    fn visit_variable(&mut self, name: &str, host: bool) -> Self::Output {
+        if host {
+            format!("@{}", name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    fn visit_let(&mut self, name: &str, value: &Expr, body: &Expr) -> Self::Output {
+        let value_str = self.visit_expr(value);
+        let body_str = self.visit_expr(body);
+        format!("(let {} = {}; {})", name, value_str, body_str)
+    }
+
+    fn visit_if(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> Self::Output {
+        let condition_str = self.visit_expr(condition);
+        let then_str = self.visit_expr(then_branch);
+        let else_str = self.visit_expr(else_branch);
+        format!("(if {} then {} else {})", condition_str, then_str, else_str)
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use core::i64;
-
     use super::*;
 
     #[test]
@@ -1,19 +1,50 @@
 #[cfg(not(feature = "std"))]
 extern crate alloc;
 #[cfg(not(feature = "std"))]
-use alloc::{boxed::Box, vec, vec::Vec};
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
 
 use core::fmt;
 
-use rand::{
-    Rng,
-    seq::{IndexedRandom, SliceRandom},
-};
+use rand::{Rng, seq::SliceRandom};
 
 #[cfg(feature = "std")]
 use rand::rng;
 
-use crate::ast::{BinaryOperator, Expr, ExprVisitor, Literal, RangeLiteral};
+use crate::ast::{
+    BinaryOperator, DiceModifier, Expr, ExprVisitor, Literal, RangeLiteral, Span, UnaryOperator,
+    walk_expr,
+};
+
+mod arith;
+mod builtins;
+mod dice;
+mod functions;
+mod limits;
+mod optimize;
+mod plan;
+mod trace;
+mod typecheck;
+
+pub use arith::ArithmeticMode;
+pub(crate) use dice::Modifiers;
+pub use functions::{CallContext, FunctionRegistry};
+pub use limits::EvalLimits;
+pub use optimize::{
+    is_deterministic, is_deterministic_with_context, optimize, optimize_with_context,
+};
+pub use plan::EvalPlan;
+#[cfg(feature = "std")]
+pub use trace::eval_traced;
+pub use trace::{DieRoll, TraceNode, eval_traced_with, eval_traced_with_context};
+pub use typecheck::{TypeError, TypeErrorKind, ValueType, typecheck, typecheck_with_context};
 
 /// The result of evaluating a [RollKit expression](Expr).
 ///
@@ -99,6 +130,8 @@ impl Value {
     }
 
     /// Gets the sum of all elements in the value. If it's an integer, it returns that integer.
+    /// The sum wraps around on overflow, see [`checked_sum`](Value::checked_sum) for a checked
+    /// version.
     ///
     /// # Examples
     ///
@@ -113,7 +146,24 @@ impl Value {
     pub fn sum(&self) -> i64 {
         match self {
             Value::Integer(i) => *i,
-            Value::List(lst) => lst.iter().sum(),
+            Value::List(lst) => lst.iter().fold(0, |acc, &i| acc.wrapping_add(i)),
+        }
+    }
+
+    /// Gets the sum of all elements in the value like [`sum`](Value::sum), or `None` if the sum
+    /// overflows.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rollkit::Value;
+    /// assert_eq!(Value::List(vec![1, 2, 3]).checked_sum(), Some(6));
+    /// assert_eq!(Value::List(vec![i64::MAX, 1]).checked_sum(), None);
+    /// ```
+    pub fn checked_sum(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            Value::List(lst) => lst.iter().try_fold(0i64, |acc, &i| acc.checked_add(i)),
         }
     }
 }
@@ -162,17 +212,25 @@ pub enum ListInner {
 }
 
 impl ListInner {
-    /// Returns the sum of all elements in the list.
-    pub fn sum(&self) -> i64 {
+    /// Returns the sum of all elements in the list, handling overflow according to `arith`.
+    pub fn sum(&self, arith: ArithmeticMode) -> Result<i64, EvalErrorKind> {
         match self {
-            ListInner::List(lst) => lst.iter().sum(),
-            ListInner::Range(range) => range.to_iter().sum(),
+            ListInner::List(lst) => arith.sum(lst.iter().copied()),
+            ListInner::Range(range) => arith.sum(range.to_iter()),
         }
     }
 
-    /// Clones the list into a concrete Vec<i64>.
-    pub fn clone_vec(&self) -> Vec<i64> {
-        self.clone().into_vec()
+    /// Returns the number of elements in the list.
+    pub fn len(&self) -> usize {
+        match self {
+            ListInner::List(lst) => lst.len(),
+            ListInner::Range(RangeLiteral { start, end, step }) => {
+                // computed directly, as ranges can be too long to iterate over
+                let span = (*end as i128 - *start as i128).unsigned_abs();
+                let step = step.map_or(1, i64::unsigned_abs).max(1) as u128;
+                usize::try_from(span / step + 1).unwrap_or(usize::MAX)
+            }
+        }
     }
 
     /// Converts the list into a concrete Vec<i64>.
@@ -182,28 +240,6 @@ impl ListInner {
             ListInner::Range(range) => range.to_iter().collect(),
         }
     }
-
-    /// Samples `count` random elements from the list using the provided random number generator.
-    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R, count: i64) -> Vec<i64> {
-        let mut sampler: Box<dyn FnMut() -> i64> = match self {
-            ListInner::List(lst) => Box::new(|| *lst.choose(rng).unwrap()),
-            ListInner::Range(RangeLiteral { start, end, step }) => {
-                if step.is_none_or(|step| step.wrapping_abs() == 1) {
-                    let range = if end >= start {
-                        *start..=*end
-                    } else {
-                        *end..=*start
-                    };
-                    Box::new(move || rng.random_range(range.clone()))
-                } else {
-                    let values: Vec<i64> = self.clone_vec();
-                    Box::new(move || *values.choose(rng).unwrap())
-                }
-            }
-        };
-
-        (0..count).map(|_| sampler()).collect()
-    }
 }
 
 /// The internal representation of a value during evaluation.
@@ -217,35 +253,117 @@ enum InnerValue {
 
 impl InnerValue {
     /// Asserts that the value is an integer and returns it, or returns an error.
-    pub fn assert_integer(self) -> Result<i64, EvalError> {
+    pub fn assert_integer(self) -> Result<i64, EvalErrorKind> {
         match self {
             InnerValue::Integer(i) => Ok(i),
-            _ => Err(EvalError::IntegerExpected),
+            _ => Err(EvalErrorKind::IntegerExpected),
         }
     }
 
     /// Asserts that the value is a list and returns its inner representation and strength, or
     /// returns an error.
-    pub fn assert_list(self) -> Result<(bool, ListInner), EvalError> {
+    pub fn assert_list(self) -> Result<(bool, ListInner), EvalErrorKind> {
         match self {
             InnerValue::List { strong, inner } => Ok((strong, inner)),
-            _ => Err(EvalError::ListExpected),
+            _ => Err(EvalErrorKind::ListExpected),
         }
     }
 
-    /// Tries to convert the value into an integer. If it's a strong list, returns the inner
-    /// representation of the list as an error.
-    pub fn try_into_integer(self) -> Result<i64, ListInner> {
+    /// Tries to convert the value into an integer, summing up weak lists with `arith`. If it's a
+    /// strong list, returns the inner representation of the list as an error.
+    pub fn try_into_integer(
+        self,
+        arith: ArithmeticMode,
+    ) -> Result<Result<i64, ListInner>, EvalErrorKind> {
         match self {
-            InnerValue::Integer(i) => Ok(i),
+            InnerValue::Integer(i) => Ok(Ok(i)),
             InnerValue::List {
                 strong: false,
                 inner,
-            } => Ok(inner.sum()),
+            } => inner.sum(arith).map(Ok),
             InnerValue::List {
                 strong: true,
                 inner,
-            } => Err(inner),
+            } => Ok(Err(inner)),
+        }
+    }
+
+    /// Converts the value into the truth of a condition, i.e., whether it's non-zero, summing up
+    /// weak lists with `arith`. Strong lists are not allowed.
+    pub fn into_condition(self, arith: ArithmeticMode) -> Result<bool, EvalErrorKind> {
+        let condition = self.try_into_integer(arith)?;
+        Ok(condition.map_err(|_| EvalErrorKind::IntegerExpected)? != 0)
+    }
+
+    /// Converts the value into a concrete Vec<i64>. If it's an integer, it returns a
+    /// single-element vector containing that integer.
+    pub fn into_vec(self) -> Vec<i64> {
+        match self {
+            InnerValue::Integer(i) => vec![i],
+            InnerValue::List { inner, .. } => inner.into_vec(),
+        }
+    }
+
+    /// Applies `op` to an integer, or element-wise to a strong list. Weak lists are reduced to
+    /// their sum first, the same way arithmetic operators treat them.
+    pub fn map_elementwise(
+        self,
+        arith: ArithmeticMode,
+        op: impl Fn(i64) -> Result<i64, EvalErrorKind>,
+    ) -> Result<InnerValue, EvalErrorKind> {
+        match self.try_into_integer(arith)? {
+            Ok(i) => Ok(InnerValue::Integer(op(i)?)),
+            Err(list) => {
+                let mut vec = list.into_vec();
+                for i in &mut vec {
+                    *i = op(*i)?;
+                }
+                Ok(InnerValue::List {
+                    strong: true,
+                    inner: ListInner::List(vec),
+                })
+            }
+        }
+    }
+
+    /// Converts the value into a strong list, like the strong list expression `{...}` does. An
+    /// integer becomes a weak list of itself.
+    pub fn into_strong(self) -> InnerValue {
+        match self {
+            InnerValue::Integer(i) => InnerValue::List {
+                strong: false,
+                inner: ListInner::List(vec![i]),
+            },
+            InnerValue::List { inner, .. } => InnerValue::List {
+                strong: true,
+                inner,
+            },
+        }
+    }
+
+    /// Gets the value of a literal. List literals become weak lists.
+    fn from_literal(literal: &Literal) -> Self {
+        match literal {
+            Literal::Int(n) => InnerValue::Integer(*n),
+            Literal::List(lst) => InnerValue::List {
+                strong: false,
+                inner: ListInner::List(lst.clone()),
+            },
+            Literal::Range(range) => InnerValue::List {
+                strong: false,
+                inner: ListInner::Range(*range),
+            },
+        }
+    }
+
+    /// Converts a public [`Value`] into the internal representation. Lists become weak lists.
+    fn from_public(value: Value) -> Self {
+        match value {
+            Value::Integer(i) => InnerValue::Integer(i),
+            Value::List(lst) => InnerValue::List {
+                strong: false,
+                inner: ListInner::List(lst),
+            },
         }
     }
 
@@ -258,9 +376,9 @@ impl InnerValue {
     }
 }
 
-/// Errors that can occur during evaluation.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum EvalError {
+/// The kinds of [errors](EvalError) that can occur during evaluation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalErrorKind {
     /// An integer was expected but a different type was found.
     IntegerExpected,
     /// A list was expected but a different type was found.
@@ -296,14 +414,96 @@ pub enum EvalError {
         /// Length of the list on the right side.
         right_len: usize,
     },
+    /// Called a function that does not exist.
+    UnknownFunction {
+        /// Name of the function.
+        name: String,
+    },
+    /// Referred to a variable that is not bound, e.g., `@str_mod` without a value in
+    /// [`EvalContext::variables`].
+    UnboundVariable {
+        /// Name of the variable as written in the expression, i.e., with the `@` of host
+        /// variables.
+        name: String,
+    },
+    /// Called a function with the wrong number or types of arguments.
+    WrongArguments {
+        /// Name of the function.
+        name: String,
+        /// Description of the arguments the function accepts.
+        expected: &'static str,
+    },
+    /// Applied a function to an empty list which requires at least one element.
+    EmptyList {
+        /// Name of the function.
+        name: String,
+    },
+    /// Divided by zero.
+    DivisionByZero,
+    /// Raised an integer to a negative power, which doesn't produce an integer in general.
+    NegativeExponent,
+    /// An operation overflowed with [`ArithmeticMode::Checked`].
+    Overflow {
+        /// Name of the operation, e.g., `"addition"`.
+        operation: &'static str,
+    },
+    /// The reroll conditions of a dice roll match every face of the die, so rerolling would never
+    /// stop.
+    UnsatisfiableReroll,
+    /// A roll was rerolled more times than allowed by [`EvalContext::reroll_limit`].
+    RerollLimitExceeded {
+        /// The maximum number of rerolls allowed.
+        limit: usize,
+    },
+    /// A dice roll requested a negative number of dice.
+    NegativeDiceCount {
+        /// The number of dice requested.
+        count: i64,
+    },
+    /// A dice roll was given a non-positive integer as the number of sides.
+    InvalidSides {
+        /// The number of sides given.
+        sides: i64,
+    },
+    /// A dice roll was given an empty list of faces.
+    EmptyDie,
+    /// A dice roll requested more dice than allowed by [`EvalLimits::max_dice`].
+    TooManyDice {
+        /// The maximum number of dice allowed.
+        limit: usize,
+        /// The number of dice requested.
+        requested: i64,
+    },
+    /// A list longer than allowed by [`EvalLimits::max_list_len`] was produced.
+    ListTooLong {
+        /// The maximum length allowed.
+        limit: usize,
+        /// The length of the list.
+        len: usize,
+    },
+    /// More random draws were made than allowed by [`EvalLimits::max_draws`].
+    DrawLimitExceeded {
+        /// The maximum number of draws allowed.
+        limit: usize,
+    },
+    /// Expressions were nested deeper than allowed by [`EvalLimits::max_depth`].
+    DepthLimitExceeded {
+        /// The maximum depth allowed.
+        limit: usize,
+    },
+    /// More expression nodes were evaluated than allowed by [`EvalLimits::max_steps`].
+    StepLimitExceeded {
+        /// The maximum number of steps allowed.
+        limit: usize,
+    },
 }
 
-impl fmt::Display for EvalError {
+impl fmt::Display for EvalErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            EvalError::IntegerExpected => write!(f, "Expected an integer, but got a list"),
-            EvalError::ListExpected => write!(f, "Expected a list, but got an integer"),
-            EvalError::KeepTooMany {
+            EvalErrorKind::IntegerExpected => write!(f, "Expected an integer, but got a list"),
+            EvalErrorKind::ListExpected => write!(f, "Expected a list, but got an integer"),
+            EvalErrorKind::KeepTooMany {
                 available,
                 requested,
             } => write!(
@@ -311,7 +511,7 @@ impl fmt::Display for EvalError {
                 "Cannot keep {} elements from a list of {} elements",
                 requested, available
             ),
-            EvalError::DropTooMany {
+            EvalErrorKind::DropTooMany {
                 available,
                 requested,
             } => write!(
@@ -319,21 +519,21 @@ impl fmt::Display for EvalError {
                 "Cannot drop {} elements from a list of {} elements",
                 requested, available
             ),
-            EvalError::KeepTooLess { requested } => {
+            EvalErrorKind::KeepTooLess { requested } => {
                 write!(
                     f,
                     "Cannot keep {} elements (must be non-negative)",
                     requested
                 )
             }
-            EvalError::DropTooLess { requested } => {
+            EvalErrorKind::DropTooLess { requested } => {
                 write!(
                     f,
                     "Cannot drop {} elements (must be non-negative)",
                     requested
                 )
             }
-            EvalError::ListMismatch {
+            EvalErrorKind::ListMismatch {
                 left_len,
                 right_len,
             } => write!(
@@ -341,6 +541,153 @@ impl fmt::Display for EvalError {
                 "List length mismatch: left has {} elements, right has {} elements",
                 left_len, right_len
             ),
+            EvalErrorKind::UnknownFunction { name } => write!(f, "Unknown function: {}", name),
+            EvalErrorKind::UnboundVariable { name } => write!(f, "Unbound variable: {}", name),
+            EvalErrorKind::WrongArguments { name, expected } => write!(
+                f,
+                "Wrong arguments for function {}, expected {}",
+                name, expected
+            ),
+            EvalErrorKind::EmptyList { name } => {
+                write!(f, "Function {} cannot be applied to an empty list", name)
+            }
+            EvalErrorKind::DivisionByZero => write!(f, "Division by zero"),
+            EvalErrorKind::NegativeExponent => write!(f, "Negative exponent"),
+            EvalErrorKind::Overflow { operation } => write!(f, "Integer overflow in {}", operation),
+            EvalErrorKind::UnsatisfiableReroll => {
+                write!(f, "Reroll conditions match every face of the die")
+            }
+            EvalErrorKind::RerollLimitExceeded { limit } => {
+                write!(f, "A die was rerolled more than {} times", limit)
+            }
+            EvalErrorKind::NegativeDiceCount { count } => {
+                write!(f, "Cannot roll a negative number of dice: {}", count)
+            }
+            EvalErrorKind::InvalidSides { sides } => {
+                write!(f, "A die must have at least one side, got {}", sides)
+            }
+            EvalErrorKind::EmptyDie => write!(f, "Cannot roll a die without faces"),
+            EvalErrorKind::TooManyDice { limit, requested } => write!(
+                f,
+                "Too many dice: requested {}, but at most {} are allowed",
+                requested, limit
+            ),
+            EvalErrorKind::ListTooLong { limit, len } => write!(
+                f,
+                "List too long: {} elements, but at most {} are allowed",
+                len, limit
+            ),
+            EvalErrorKind::DrawLimitExceeded { limit } => {
+                write!(f, "More than {} dice were rolled", limit)
+            }
+            EvalErrorKind::DepthLimitExceeded { limit } => {
+                write!(f, "Expression nested deeper than {} levels", limit)
+            }
+            EvalErrorKind::StepLimitExceeded { limit } => {
+                write!(f, "Evaluation took more than {} steps", limit)
+            }
+        }
+    }
+}
+
+/// An error that occurred during evaluation, with the span of the failing sub-expression.
+///
+/// Errors created by [user-defined functions](FunctionRegistry) don't need a span, the span of the
+/// function call is attached to them automatically. Errors can be created from an
+/// [`EvalErrorKind`] with [`From`].
+///
+/// # Example
+///
+/// ```
+/// # use rollkit::{EvalErrorKind, eval, parse};
+/// let input = "1 + 4d6kh5";
+/// let error = eval(&parse(input).unwrap()).unwrap_err();
+/// assert!(matches!(error.kind(), EvalErrorKind::KeepTooMany { .. }));
+/// assert_eq!(&input[error.span().unwrap().into_range()], "4d6kh5");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalError {
+    kind: EvalErrorKind,
+    span: Option<Span>,
+}
+
+impl EvalError {
+    /// Creates an error of the given kind, with the span of the failing sub-expression if known.
+    pub fn new(kind: EvalErrorKind, span: Option<Span>) -> Self {
+        Self { kind, span }
+    }
+
+    /// Returns the kind of the error.
+    pub fn kind(&self) -> &EvalErrorKind {
+        &self.kind
+    }
+
+    /// Converts the error into its kind.
+    pub fn into_kind(self) -> EvalErrorKind {
+        self.kind
+    }
+
+    /// Returns the span of the sub-expression that failed, or `None` if it's unknown, e.g., for
+    /// errors created outside of an evaluation.
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    /// Attaches the span to the error, unless it already has one from a more specific
+    /// sub-expression.
+    fn or_span(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+}
+
+impl From<EvalErrorKind> for EvalError {
+    fn from(kind: EvalErrorKind) -> Self {
+        Self::new(kind, None)
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl core::error::Error for EvalError {}
+
+/// The context of an evaluation, holding everything besides the random number generator that
+/// affects how expressions are evaluated.
+///
+/// The default context is used by [`eval`] and [`eval_with`]. Use [`eval_with_context`] to
+/// evaluate with a custom one.
+#[derive(Debug)]
+pub struct EvalContext {
+    /// User-defined functions callable from expressions, in addition to the built-in ones.
+    pub functions: FunctionRegistry,
+    /// The values of host variables, referred to by name with `@` in expressions, e.g.,
+    /// `1d20 + @str_mod`. Lists are weak lists.
+    pub variables: BTreeMap<String, Value>,
+    /// The maximum number of times a single die can explode, to stop expressions like `1d1!` from
+    /// exploding forever. Defaults to 100.
+    pub explode_limit: usize,
+    /// The maximum number of times a single roll can be rerolled by the reroll modifier `r`,
+    /// before the evaluation fails with [`EvalErrorKind::RerollLimitExceeded`]. Defaults to 100.
+    pub reroll_limit: usize,
+    /// How integer overflow is handled. Defaults to [`ArithmeticMode::Checked`].
+    pub arithmetic: ArithmeticMode,
+    /// Limits on the resources used by the evaluation. Defaults to [`EvalLimits::default`].
+    pub limits: EvalLimits,
+}
+
+impl Default for EvalContext {
+    fn default() -> Self {
+        Self {
+            functions: FunctionRegistry::default(),
+            variables: BTreeMap::new(),
+            explode_limit: 100,
+            reroll_limit: 100,
+            arithmetic: ArithmeticMode::default(),
+            limits: EvalLimits::default(),
         }
     }
 }
@@ -351,16 +698,44 @@ where
     R: Rng + ?Sized,
 {
     rng: &'a mut R,
+    context: &'a EvalContext,
+    usage: limits::Usage,
+    /// Records the evaluation if tracing.
+    tracer: Option<trace::Tracer>,
+    /// The values of the variables bound by `let`, innermost last.
+    scope: Vec<(String, InnerValue)>,
+}
+
+impl<R> functions::ArgEvaluator for EvalVisitor<'_, R>
+where
+    R: Rng + ?Sized,
+{
+    fn eval_arg(&mut self, expr: &Expr) -> Result<Value, EvalError> {
+        self.visit_expr(expr).map(InnerValue::into_public)
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dst: &mut [u8]) {
+        self.rng.fill_bytes(dst)
+    }
 }
 
-/// Evaluates keep/drop operations on lists.
+/// Evaluates keep/drop operations on lists. The elements removed from the list are recorded if
+/// tracing.
 fn eval_keep_drop_op<R: Rng + ?Sized>(
+    visitor: &mut EvalVisitor<'_, R>,
     left: InnerValue,
     right: InnerValue,
     keep: bool,
     highest: bool,
-    rng: &mut R,
-) -> Result<InnerValue, EvalError> {
+) -> Result<InnerValue, EvalErrorKind> {
     let (strong, list) = left.assert_list()?;
     let mut vec = list.into_vec();
     let requested = right.assert_integer()?;
@@ -368,20 +743,20 @@ fn eval_keep_drop_op<R: Rng + ?Sized>(
 
     if requested < 0 {
         return if keep {
-            Err(EvalError::KeepTooLess { requested })
+            Err(EvalErrorKind::KeepTooLess { requested })
         } else {
-            Err(EvalError::DropTooLess { requested })
+            Err(EvalErrorKind::DropTooLess { requested })
         };
     }
 
     if requested as usize > available {
         return if keep {
-            Err(EvalError::KeepTooMany {
+            Err(EvalErrorKind::KeepTooMany {
                 available,
                 requested,
             })
         } else {
-            Err(EvalError::DropTooMany {
+            Err(EvalErrorKind::DropTooMany {
                 available,
                 requested,
             })
@@ -390,13 +765,17 @@ fn eval_keep_drop_op<R: Rng + ?Sized>(
 
     vec.sort_unstable_by(|a, b| if keep ^ highest { a.cmp(b) } else { b.cmp(a) });
 
-    vec.truncate(if keep {
+    let len = if keep {
         requested as usize
     } else {
         available - requested as usize
-    });
+    };
+    if let Some(tracer) = &mut visitor.tracer {
+        tracer.drop(&vec[len..]);
+    }
+    vec.truncate(len);
 
-    vec.shuffle(rng);
+    vec.shuffle(visitor.rng);
 
     Ok(InnerValue::List {
         strong,
@@ -405,17 +784,24 @@ fn eval_keep_drop_op<R: Rng + ?Sized>(
 }
 
 /// Evaluates arithmetic and comparison operations on integers and lists.
-fn eval_arith_cmp_op(
+///
+/// Weak lists are reduced to their sum first. If either operand is a strong list, the operation is
+/// applied element-wise, and the result is a strong list.
+fn eval_elementwise_op(
+    arith: ArithmeticMode,
     left: InnerValue,
     right: InnerValue,
-    op: fn(i64, i64) -> i64,
-) -> Result<InnerValue, EvalError> {
-    match (left.try_into_integer(), right.try_into_integer()) {
-        (Ok(l), Ok(r)) => Ok(InnerValue::Integer(op(l, r))),
+    op: impl Fn(i64, i64) -> Result<i64, EvalErrorKind>,
+) -> Result<InnerValue, EvalErrorKind> {
+    match (
+        left.try_into_integer(arith)?,
+        right.try_into_integer(arith)?,
+    ) {
+        (Ok(l), Ok(r)) => Ok(InnerValue::Integer(op(l, r)?)),
         (Ok(l), Err(list)) => {
             let mut vec = list.into_vec();
             for r in &mut vec {
-                *r = op(l, *r);
+                *r = op(l, *r)?;
             }
             Ok(InnerValue::List {
                 strong: true,
@@ -425,7 +811,7 @@ fn eval_arith_cmp_op(
         (Err(list), Ok(r)) => {
             let mut vec = list.into_vec();
             for l in &mut vec {
-                *l = op(*l, r);
+                *l = op(*l, r)?;
             }
             Ok(InnerValue::List {
                 strong: true,
@@ -436,13 +822,17 @@ fn eval_arith_cmp_op(
             let lvec = list.into_vec();
             let rvec = rlist.into_vec();
             if lvec.len() != rvec.len() {
-                return Err(EvalError::ListMismatch {
+                return Err(EvalErrorKind::ListMismatch {
                     left_len: lvec.len(),
                     right_len: rvec.len(),
                 });
             }
 
-            let vec: Vec<i64> = lvec.into_iter().zip(rvec).map(|(l, r)| op(l, r)).collect();
+            let vec = lvec
+                .into_iter()
+                .zip(rvec)
+                .map(|(l, r)| op(l, r))
+                .collect::<Result<Vec<_>, _>>()?;
 
             Ok(InnerValue::List {
                 strong: true,
@@ -452,10 +842,20 @@ fn eval_arith_cmp_op(
     }
 }
 
+/// Evaluates arithmetic operations on integers and lists with [`eval_elementwise_op`].
+fn eval_arith_op(
+    arith: ArithmeticMode,
+    left: InnerValue,
+    right: InnerValue,
+    op: fn(ArithmeticMode, i64, i64) -> Result<i64, EvalErrorKind>,
+) -> Result<InnerValue, EvalErrorKind> {
+    eval_elementwise_op(arith, left, right, |l, r| op(arith, l, r))
+}
+
 /// A wrapper macro to create "0-1" comparison operations.
 macro_rules! bi_cmp_op {
     ($op:tt) => {
-        |a: i64, b: i64| if a $op b { 1 } else { 0 }
+        |a: i64, b: i64| Ok(if a $op b { 1 } else { 0 })
     };
 }
 
@@ -465,75 +865,190 @@ where
 {
     type Output = Result<InnerValue, EvalError>;
 
+    fn visit_expr(&mut self, expr: &Expr) -> Self::Output {
+        self.enter(expr.span)?;
+        let result = walk_expr(self, expr);
+        self.leave(expr.span, result)
+    }
+
     fn visit_literal(&mut self, literal: &Literal) -> Self::Output {
-        Ok(match literal {
-            Literal::Int(n) => InnerValue::Integer(*n),
-            Literal::List(lst) => InnerValue::List {
-                strong: false,
-                inner: ListInner::List(lst.clone()),
-            },
-            Literal::Range(range) => InnerValue::List {
-                strong: false,
-                inner: ListInner::Range(*range),
-            },
-        })
+        Ok(InnerValue::from_literal(literal))
+    }
+
+    fn visit_unary_op(&mut self, op: &UnaryOperator, expr: &Expr) -> Self::Output {
+        let value = self.visit_expr(expr)?;
+        Ok(self.unary_op(op, value)?)
     }
 
     fn visit_binary_op(&mut self, left: &Expr, op: &BinaryOperator, right: &Expr) -> Self::Output {
         let left = self.visit_expr(left)?;
         let right = self.visit_expr(right)?;
+        Ok(self.binary_op(op, left, right)?)
+    }
 
-        match op {
-            BinaryOperator::DiceRoll => {
-                let count = left.assert_integer()?;
-                let sides = match right {
-                    InnerValue::Integer(n) => ListInner::Range(RangeLiteral {
-                        start: 1,
-                        end: n,
-                        step: None,
-                    }),
-                    InnerValue::List { inner, .. } => inner,
-                };
-
-                Ok(InnerValue::List {
-                    strong: false,
-                    inner: ListInner::List(sides.sample(self.rng, count)),
-                })
-            }
-            BinaryOperator::KeepHighest => eval_keep_drop_op(left, right, true, true, self.rng),
-            BinaryOperator::KeepLowest => eval_keep_drop_op(left, right, true, false, self.rng),
-            BinaryOperator::DropHighest => eval_keep_drop_op(left, right, false, true, self.rng),
-            BinaryOperator::DropLowest => eval_keep_drop_op(left, right, false, false, self.rng),
-            BinaryOperator::Multiplication => eval_arith_cmp_op(left, right, i64::wrapping_mul),
-            BinaryOperator::Addition => eval_arith_cmp_op(left, right, i64::wrapping_add),
-            BinaryOperator::Subtraction => eval_arith_cmp_op(left, right, i64::wrapping_sub),
-            BinaryOperator::Equal => eval_arith_cmp_op(left, right, bi_cmp_op!(==)),
-            BinaryOperator::NotEqual => eval_arith_cmp_op(left, right, bi_cmp_op!(!=)),
-            BinaryOperator::LessThan => eval_arith_cmp_op(left, right, bi_cmp_op!(<)),
-            BinaryOperator::LessEqual => eval_arith_cmp_op(left, right, bi_cmp_op!(<=)),
-            BinaryOperator::GreaterThan => eval_arith_cmp_op(left, right, bi_cmp_op!(>)),
-            BinaryOperator::GreaterEqual => eval_arith_cmp_op(left, right, bi_cmp_op!(>=)),
-        }
+    fn visit_modified_dice(
+        &mut self,
+        count: &Expr,
+        sides: &Expr,
+        modifiers: &[DiceModifier],
+    ) -> Self::Output {
+        let count = self.visit_expr(count)?;
+        let sides = self.visit_expr(sides)?;
+        Ok(dice::roll_dice(self, count, sides, modifiers)?)
     }
 
     fn visit_function_call(&mut self, name: &str, args: &[Expr]) -> Self::Output {
-        todo!(
-            "Function calls are not yet implemented: {}, {:?}",
-            name,
-            args
-        )
+        let context = self.context;
+        if let Some(result) = context.functions.call(name, args, self) {
+            return result.map(InnerValue::from_public);
+        }
+
+        let builtin = builtins::lookup(name).ok_or_else(|| EvalErrorKind::UnknownFunction {
+            name: name.to_string(),
+        })?;
+        let args = args
+            .iter()
+            .map(|arg| self.visit_expr(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok((builtin.func)(builtin, args, self.context.arithmetic)?)
     }
 
     fn visit_strong_list(&mut self, expr: &Expr) -> Self::Output {
-        match self.visit_expr(expr)? {
-            InnerValue::Integer(i) => Ok(InnerValue::List {
-                strong: false,
-                inner: ListInner::List(vec![i]),
-            }),
-            InnerValue::List { inner, .. } => Ok(InnerValue::List {
-                strong: true,
-                inner,
-            }),
+        Ok(self.visit_expr(expr)?.into_strong())
+    }
+
+    fn visit_variable(&mut self, name: &str, host: bool) -> Self::Output {
+        Ok(self.variable(name, host)?)
+    }
+
+    fn visit_let(&mut self, name: &str, value: &Expr, body: &Expr) -> Self::Output {
+        let value = self.visit_expr(value)?;
+        self.scope.push((name.to_string(), value));
+        let result = self.visit_expr(body);
+        self.scope.pop();
+        result
+    }
+
+    fn visit_if(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> Self::Output {
+        let condition = self.visit_expr(condition)?;
+        // only the branch taken is evaluated, so the dice of the other one are not rolled
+        if condition.into_condition(self.context.arithmetic)? {
+            self.visit_expr(then_branch)
+        } else {
+            self.visit_expr(else_branch)
+        }
+    }
+}
+
+impl<R> EvalVisitor<'_, R>
+where
+    R: Rng + ?Sized,
+{
+    /// Records entering the evaluation of an expression node, checking the limits.
+    fn enter(&mut self, span: Span) -> Result<(), EvalError> {
+        self.usage
+            .enter()
+            .map_err(|kind| EvalError::new(kind, Some(span)))?;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.enter(span);
+        }
+        Ok(())
+    }
+
+    /// Records leaving the evaluation of an expression node with its result, checking the length
+    /// of the resulting list and attaching the span of the node to errors.
+    fn leave(
+        &mut self,
+        span: Span,
+        result: Result<InnerValue, EvalError>,
+    ) -> Result<InnerValue, EvalError> {
+        self.usage.leave();
+
+        // errors from sub-expressions already carry their spans
+        let result = result
+            .and_then(|value| {
+                if let InnerValue::List { inner, .. } = &value {
+                    self.usage.check_list_len(inner.len())?;
+                }
+                Ok(value)
+            })
+            .map_err(|error| error.or_span(span));
+        if let Some(tracer) = &mut self.tracer {
+            tracer.leave(result.as_ref().ok().cloned().map(InnerValue::into_public));
+        }
+        result
+    }
+
+    /// Looks up the value of a variable, either bound by `let` or a host variable.
+    fn variable(&self, name: &str, host: bool) -> Result<InnerValue, EvalErrorKind> {
+        let value = if host {
+            let value = self.context.variables.get(name);
+            value.cloned().map(InnerValue::from_public)
+        } else {
+            let mut bindings = self.scope.iter().rev();
+            let binding = bindings.find(|(bound, _)| bound == name);
+            binding.map(|(_, value)| value.clone())
+        };
+        value.ok_or_else(|| EvalErrorKind::UnboundVariable {
+            name: if host {
+                format!("@{}", name)
+            } else {
+                name.to_string()
+            },
+        })
+    }
+
+    /// Applies a unary operator to an evaluated operand.
+    fn unary_op(&self, op: &UnaryOperator, value: InnerValue) -> Result<InnerValue, EvalErrorKind> {
+        let arith = self.context.arithmetic;
+        match op {
+            UnaryOperator::Negation => value.map_elementwise(arith, |i| arith.neg(i)),
+            UnaryOperator::Plus => value.map_elementwise(arith, Ok),
+        }
+    }
+
+    /// Applies a binary operator to evaluated operands.
+    fn binary_op(
+        &mut self,
+        op: &BinaryOperator,
+        left: InnerValue,
+        right: InnerValue,
+    ) -> Result<InnerValue, EvalErrorKind> {
+        let arith = self.context.arithmetic;
+        match op {
+            BinaryOperator::DiceRoll => dice::roll_dice(self, left, right, &[]),
+            BinaryOperator::KeepHighest => eval_keep_drop_op(self, left, right, true, true),
+            BinaryOperator::KeepLowest => eval_keep_drop_op(self, left, right, true, false),
+            BinaryOperator::DropHighest => eval_keep_drop_op(self, left, right, false, true),
+            BinaryOperator::DropLowest => eval_keep_drop_op(self, left, right, false, false),
+            BinaryOperator::Exponentiation => {
+                eval_arith_op(arith, left, right, ArithmeticMode::pow)
+            }
+            BinaryOperator::Multiplication => {
+                eval_arith_op(arith, left, right, ArithmeticMode::mul)
+            }
+            BinaryOperator::Division => eval_arith_op(arith, left, right, ArithmeticMode::div),
+            BinaryOperator::FloorDivision => {
+                eval_arith_op(arith, left, right, ArithmeticMode::div_floor)
+            }
+            BinaryOperator::CeilDivision => {
+                eval_arith_op(arith, left, right, ArithmeticMode::div_ceil)
+            }
+            BinaryOperator::Modulo => eval_arith_op(arith, left, right, ArithmeticMode::rem),
+            BinaryOperator::Addition => eval_arith_op(arith, left, right, ArithmeticMode::add),
+            BinaryOperator::Subtraction => eval_arith_op(arith, left, right, ArithmeticMode::sub),
+            BinaryOperator::Equal => eval_elementwise_op(arith, left, right, bi_cmp_op!(==)),
+            BinaryOperator::NotEqual => eval_elementwise_op(arith, left, right, bi_cmp_op!(!=)),
+            BinaryOperator::LessThan => eval_elementwise_op(arith, left, right, bi_cmp_op!(<)),
+            BinaryOperator::LessEqual => eval_elementwise_op(arith, left, right, bi_cmp_op!(<=)),
+            BinaryOperator::GreaterThan => eval_elementwise_op(arith, left, right, bi_cmp_op!(>)),
+            BinaryOperator::GreaterEqual => eval_elementwise_op(arith, left, right, bi_cmp_op!(>=)),
         }
     }
 }
@@ -571,6 +1086,421 @@ pub fn eval(expr: &Expr) -> Result<Value, EvalError> {
 /// println!("Result: {:?}", result);
 /// ```
 pub fn eval_with<R: Rng + ?Sized>(expr: &Expr, rng: &mut R) -> Result<Value, EvalError> {
-    let mut visitor = EvalVisitor { rng };
+    eval_with_context(expr, rng, &EvalContext::default())
+}
+
+/// Evaluates a RollKit expression with a provided random number generator and
+/// [evaluation context](EvalContext), and returns the result.
+///
+/// # Examples
+///
+/// ```
+/// # use rand::{SeedableRng, rngs::StdRng};
+/// # use rollkit::{EvalContext, FunctionRegistry, Value, eval_with_context, parse};
+/// let mut functions = FunctionRegistry::new();
+/// functions.register("crit", |args| {
+///     Ok(Value::Integer(args.iter().map(|arg| arg.sum() * 2).sum()))
+/// });
+/// let context = EvalContext { functions, ..Default::default() };
+///
+/// let mut rng = StdRng::from_os_rng();
+/// let expr = parse("crit(3d6) + 2").unwrap();
+/// let result = eval_with_context(&expr, &mut rng, &context).unwrap();
+/// assert!(matches!(result, Value::Integer(n) if n % 2 == 0 && (8..=38).contains(&n)));
+/// ```
+pub fn eval_with_context<R: Rng + ?Sized>(
+    expr: &Expr,
+    rng: &mut R,
+    context: &EvalContext,
+) -> Result<Value, EvalError> {
+    let mut visitor = EvalVisitor {
+        rng,
+        context,
+        usage: limits::Usage::new(context.limits),
+        tracer: None,
+        scope: Vec::new(),
+    };
     visitor.visit_expr(expr).map(InnerValue::into_public)
 }
+
+/// Returns `true` if `name` is a built-in function.
+pub(crate) fn is_builtin(name: &str) -> bool {
+    builtins::lookup(name).is_some()
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::parse;
+
+    fn eval_str(input: &str) -> Result<Value, EvalErrorKind> {
+        eval_with(&parse(input).unwrap(), &mut StdRng::seed_from_u64(0))
+            .map_err(EvalError::into_kind)
+    }
+
+    #[test]
+    fn test_division() {
+        let cases = [
+            ("7 / 2", Ok(Value::Integer(3))),
+            ("-7 / 2", Ok(Value::Integer(-3))),
+            ("7 // 2", Ok(Value::Integer(3))),
+            ("-7 // 2", Ok(Value::Integer(-4))),
+            ("7 // -2", Ok(Value::Integer(-4))),
+            ("-8 // 2", Ok(Value::Integer(-4))),
+            ("7 /^ 2", Ok(Value::Integer(4))),
+            ("-7 /^ 2", Ok(Value::Integer(-3))),
+            ("-7 /^ -2", Ok(Value::Integer(4))),
+            ("8 /^ 2", Ok(Value::Integer(4))),
+            ("7 % 3", Ok(Value::Integer(1))),
+            ("-7 % 3", Ok(Value::Integer(-1))),
+            ("{1, 2, 3} / 2", Ok(Value::Integer(3))),
+            ("{{1, 2, 3}} // 2", Ok(Value::List(vec![0, 1, 1]))),
+            ("{{4, 5, 6}} % {{3, 3, 4}}", Ok(Value::List(vec![1, 2, 2]))),
+            (
+                "(-9223372036854775807 - 1) / -1",
+                Err(EvalErrorKind::Overflow {
+                    operation: "division",
+                }),
+            ),
+            ("1 / 0", Err(EvalErrorKind::DivisionByZero)),
+            ("1 % 0", Err(EvalErrorKind::DivisionByZero)),
+            ("{{1, 2}} /^ {{1, 0}}", Err(EvalErrorKind::DivisionByZero)),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_str(input), expected, "Input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_exponentiation() {
+        let cases = [
+            ("2 ^ 10", Ok(Value::Integer(1024))),
+            ("2 ** 3 ** 2", Ok(Value::Integer(512))),
+            ("-2 ^ 2", Ok(Value::Integer(-4))),
+            ("(-2) ^ 3", Ok(Value::Integer(-8))),
+            ("7 ^ 0", Ok(Value::Integer(1))),
+            ("0 ^ 0", Ok(Value::Integer(1))),
+            ("3d1 ^ 2", Ok(Value::Integer(9))),
+            ("2 * 3 ^ 2", Ok(Value::Integer(18))),
+            ("{{1, 2, 3}} ^ 2", Ok(Value::List(vec![1, 4, 9]))),
+            ("2 ^ 62", Ok(Value::Integer(1 << 62))),
+            ("(-2) ^ 63", Ok(Value::Integer(i64::MIN))),
+            ("-1 ^ 9223372036854775807", Ok(Value::Integer(-1))),
+            ("(-1) ^ 9223372036854775807", Ok(Value::Integer(-1))),
+            ("2 ^ -1", Err(EvalErrorKind::NegativeExponent)),
+            ("1 ^ -1", Err(EvalErrorKind::NegativeExponent)),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_str(input), expected, "Input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_arithmetic_modes() {
+        let eval_in_mode = |input: &str, arithmetic: ArithmeticMode| {
+            let context = EvalContext {
+                arithmetic,
+                ..Default::default()
+            };
+            eval_with_context(
+                &parse(input).unwrap(),
+                &mut StdRng::seed_from_u64(0),
+                &context,
+            )
+            .map_err(EvalError::into_kind)
+        };
+        let overflow = |operation| Err(EvalErrorKind::Overflow { operation });
+
+        // (input, wrapping, saturating, checked)
+        let cases = [
+            (
+                "9223372036854775807 + 1",
+                Ok(Value::Integer(i64::MIN)),
+                Ok(Value::Integer(i64::MAX)),
+                overflow("addition"),
+            ),
+            (
+                "-9223372036854775807 - 2",
+                Ok(Value::Integer(i64::MAX)),
+                Ok(Value::Integer(i64::MIN)),
+                overflow("subtraction"),
+            ),
+            (
+                "-3037000500 * 3037000500",
+                Ok(Value::Integer(-3037000500i64.wrapping_mul(3037000500))),
+                Ok(Value::Integer(i64::MIN)),
+                overflow("multiplication"),
+            ),
+            (
+                "(-9223372036854775807 - 1) // -1",
+                Ok(Value::Integer(i64::MIN)),
+                Ok(Value::Integer(i64::MAX)),
+                overflow("division"),
+            ),
+            (
+                "3 ^ 41",
+                Ok(Value::Integer(3i64.wrapping_pow(41))),
+                Ok(Value::Integer(i64::MAX)),
+                overflow("exponentiation"),
+            ),
+            (
+                "(-2) ^ 9223372036854775807",
+                Ok(Value::Integer(0)),
+                Ok(Value::Integer(i64::MIN)),
+                overflow("exponentiation"),
+            ),
+            (
+                "-(-9223372036854775807 - 1)",
+                Ok(Value::Integer(i64::MIN)),
+                Ok(Value::Integer(i64::MAX)),
+                overflow("negation"),
+            ),
+            (
+                "abs(-9223372036854775807 - 1)",
+                Ok(Value::Integer(i64::MIN)),
+                Ok(Value::Integer(i64::MAX)),
+                overflow("abs"),
+            ),
+            (
+                "{9223372036854775807, 1} + 0",
+                Ok(Value::Integer(i64::MIN)),
+                Ok(Value::Integer(i64::MAX)),
+                overflow("sum"),
+            ),
+            (
+                "sum([9223372036854775806, 9223372036854775807])",
+                Ok(Value::Integer(-3)),
+                Ok(Value::Integer(i64::MAX)),
+                overflow("sum"),
+            ),
+            (
+                "{{1, 9223372036854775807}} + 1",
+                Ok(Value::List(vec![2, i64::MIN])),
+                Ok(Value::List(vec![2, i64::MAX])),
+                overflow("addition"),
+            ),
+            (
+                // the die explodes until the explode limit of 100 is reached
+                "1d{9223372036854775807}!!",
+                Ok(Value::List(vec![i64::MAX.wrapping_mul(101)])),
+                Ok(Value::List(vec![i64::MAX])),
+                overflow("addition"),
+            ),
+        ];
+
+        for (input, wrapping, saturating, checked) in cases {
+            assert_eq!(
+                eval_in_mode(input, ArithmeticMode::Wrapping),
+                wrapping,
+                "Input: {}",
+                input
+            );
+            assert_eq!(
+                eval_in_mode(input, ArithmeticMode::Saturating),
+                saturating,
+                "Input: {}",
+                input
+            );
+            assert_eq!(
+                eval_in_mode(input, ArithmeticMode::Checked),
+                checked,
+                "Input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = EvalLimits {
+            max_dice: 100,
+            max_list_len: 1000,
+            max_draws: 500,
+            max_depth: 20,
+            max_steps: 50,
+        };
+        let context = EvalContext {
+            limits,
+            ..Default::default()
+        };
+        let eval_limited = |input: &str| {
+            eval_with_context(
+                &parse(input).unwrap(),
+                &mut StdRng::seed_from_u64(0),
+                &context,
+            )
+            .map_err(EvalError::into_kind)
+        };
+
+        assert_eq!(
+            eval_limited("100d6").map(Value::into_list).unwrap().len(),
+            100
+        );
+        assert_eq!(
+            eval_limited("1000000000000d6"),
+            Err(EvalErrorKind::TooManyDice {
+                limit: 100,
+                requested: 1000000000000
+            })
+        );
+        assert!(eval_limited("1d1000000000000").unwrap().sum() > 0);
+        assert_eq!(eval_limited("len([1, 1000])"), Ok(Value::Integer(1000)));
+        assert_eq!(
+            eval_limited("sum([1, 9223372036854775807])"),
+            Err(EvalErrorKind::ListTooLong {
+                limit: 1000,
+                len: 9223372036854775807
+            })
+        );
+        assert_eq!(
+            eval_limited("[-9223372036854775807, 9223372036854775807, 2]"),
+            Err(EvalErrorKind::ListTooLong {
+                limit: 1000,
+                len: 1 << 63
+            })
+        );
+        // explosions are limited by the number of draws
+        assert_eq!(
+            eval_limited("10d1!"),
+            Err(EvalErrorKind::DrawLimitExceeded { limit: 500 })
+        );
+        assert_eq!(
+            eval_limited(&format!("max({})", ["1d6"; 20].join(", "))),
+            Err(EvalErrorKind::StepLimitExceeded { limit: 50 })
+        );
+        assert_eq!(
+            eval_limited(&format!("{}1d1{}", "-(".repeat(18), ")".repeat(18))),
+            Ok(Value::Integer(1))
+        );
+        assert_eq!(
+            eval_limited(&format!("{}1d1{}", "-(".repeat(19), ")".repeat(19))),
+            Err(EvalErrorKind::DepthLimitExceeded { limit: 20 })
+        );
+    }
+
+    #[test]
+    fn test_unary() {
+        let cases = [
+            ("-5", Value::Integer(-5)),
+            ("- 5", Value::Integer(-5)),
+            ("+5", Value::Integer(5)),
+            ("3 - -2", Value::Integer(5)),
+            ("-(1 + 2) * 2", Value::Integer(-6)),
+            ("-1d1", Value::Integer(-1)),
+            ("-3d1 + 10", Value::Integer(7)),
+            ("-{1, 2, 3}", Value::Integer(-6)),
+            ("+{1, 2, 3}", Value::Integer(6)),
+            ("-{{1, -2, 3}}", Value::List(vec![-1, 2, -3])),
+            ("+{{1, 2}}", Value::List(vec![1, 2])),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_str(input), Ok(expected), "Input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut variables = BTreeMap::new();
+        variables.insert("str_mod".to_string(), Value::Integer(3));
+        variables.insert("pool".to_string(), Value::List(vec![4, 5]));
+        let mut functions = FunctionRegistry::new();
+        functions.register_lazy("twice", |args, ctx| {
+            Ok(Value::Integer(ctx.eval(&args[0])?.sum() * 2))
+        });
+        let context = EvalContext {
+            variables,
+            functions,
+            ..Default::default()
+        };
+
+        let cases = [
+            ("let x = 1d1000; x - x", Ok(Value::Integer(0))),
+            ("let x = 2; let y = x * 3; x + y", Ok(Value::Integer(8))),
+            ("let x = 1; let x = x + 1; x", Ok(Value::Integer(2))),
+            ("(let x = 1; x) + (let x = 2; x)", Ok(Value::Integer(3))),
+            ("let x = {{1, 2}}; x * 2", Ok(Value::List(vec![2, 4]))),
+            ("let x = 3d1; x", Ok(Value::List(vec![1, 1, 1]))),
+            ("1d1 - 1 + @str_mod", Ok(Value::Integer(3))),
+            ("@pool + 1", Ok(Value::Integer(10))),
+            ("{@pool} + 1", Ok(Value::List(vec![5, 6]))),
+            ("let str_mod = 1; str_mod + @str_mod", Ok(Value::Integer(4))),
+            ("let x = 5; twice(x)", Ok(Value::Integer(10))),
+            (
+                "let x = 1; y",
+                Err(EvalErrorKind::UnboundVariable {
+                    name: "y".to_string(),
+                }),
+            ),
+            (
+                "@dex_mod",
+                Err(EvalErrorKind::UnboundVariable {
+                    name: "@dex_mod".to_string(),
+                }),
+            ),
+            (
+                "(let x = 1; x) + x",
+                Err(EvalErrorKind::UnboundVariable {
+                    name: "x".to_string(),
+                }),
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let expr = parse(input).unwrap();
+            let result = eval_with_context(&expr, &mut StdRng::seed_from_u64(0), &context);
+            assert_eq!(
+                result.map_err(EvalError::into_kind),
+                expected,
+                "Input: {}",
+                input
+            );
+        }
+
+        let input = "1 + (let x = 2; x * y)";
+        let error = eval_with_context(
+            &parse(input).unwrap(),
+            &mut StdRng::seed_from_u64(0),
+            &context,
+        )
+        .unwrap_err();
+        assert_eq!(&input[error.span().unwrap().into_range()], "y");
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let cases = [
+            ("if 2 > 1 then 10 else 20", Ok(Value::Integer(10))),
+            ("if 1 - 1 then 10 else 20", Ok(Value::Integer(20))),
+            ("if [1, -1] then 10 else 20", Ok(Value::Integer(20))),
+            ("if -3 then 3d1 else 1", Ok(Value::List(vec![1, 1, 1]))),
+            ("if 0 then 1 / 0 else 5", Ok(Value::Integer(5))),
+            ("if 1 then 1 else 0 else 1", Err(())),
+        ];
+        for (input, expected) in cases {
+            let result = parse(input).map(|expr| eval(&expr).unwrap());
+            assert_eq!(result.map_err(|_| ()), expected, "Input: {}", input);
+        }
+
+        let input = "1 + (if {{1, 1}} then 1 else 2)";
+        let error = eval(&parse(input).unwrap()).unwrap_err();
+        assert_eq!(error.kind(), &EvalErrorKind::IntegerExpected);
+        assert_eq!(
+            &input[error.span().unwrap().into_range()],
+            "if {{1, 1}} then 1 else 2"
+        );
+
+        // the dice of the branch not taken are not rolled
+        let mut rng = StdRng::seed_from_u64(0);
+        let expr = parse("if 0 then 100d6 else 1").unwrap();
+        assert_eq!(eval_with(&expr, &mut rng), Ok(Value::Integer(1)));
+        assert_eq!(
+            rng.random::<u64>(),
+            StdRng::seed_from_u64(0).random::<u64>()
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Integer arithmetic with configurable overflow handling.
+
+use super::EvalErrorKind;
+
+/// How integer overflow is handled during evaluation.
+///
+/// All integers are [`i64`]. The mode applies to arithmetic operators, built-in functions, sums of
+/// lists, e.g., when a weak list is used as an integer, and dice modifiers adding up rolls.
+///
+/// # Example
+///
+/// ```
+/// # use rand::{SeedableRng, rngs::StdRng};
+/// # use rollkit::{ArithmeticMode, EvalContext, EvalErrorKind, Value, eval_with_context, parse};
+/// let expr = parse("9223372036854775807 + 1").unwrap();
+/// let mut rng = StdRng::from_os_rng();
+///
+/// let checked = EvalContext::default();
+/// assert_eq!(
+///     eval_with_context(&expr, &mut rng, &checked).unwrap_err().kind(),
+///     &EvalErrorKind::Overflow { operation: "addition" }
+/// );
+///
+/// let saturating = EvalContext { arithmetic: ArithmeticMode::Saturating, ..Default::default() };
+/// assert_eq!(eval_with_context(&expr, &mut rng, &saturating), Ok(Value::Integer(i64::MAX)));
+///
+/// let wrapping = EvalContext { arithmetic: ArithmeticMode::Wrapping, ..Default::default() };
+/// assert_eq!(eval_with_context(&expr, &mut rng, &wrapping), Ok(Value::Integer(i64::MIN)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArithmeticMode {
+    /// Results wrap around at the bounds of [`i64`], e.g., `9223372036854775807 + 1` results in
+    /// `-9223372036854775808`.
+    Wrapping,
+    /// Results are clamped to the bounds of [`i64`], e.g., `9223372036854775807 + 1` results in
+    /// `9223372036854775807`.
+    Saturating,
+    /// Overflows fail the evaluation with [`EvalErrorKind::Overflow`]. This is the default.
+    #[default]
+    Checked,
+}
+
+impl ArithmeticMode {
+    /// Converts the exact result of an operation to [`i64`], handling overflow according to the
+    /// mode. `operation` names the operation in the error.
+    fn narrow(self, operation: &'static str, value: i128) -> Result<i64, EvalErrorKind> {
+        match self {
+            ArithmeticMode::Wrapping => Ok(value as i64),
+            ArithmeticMode::Saturating => {
+                Ok(value.clamp(i64::MIN as i128, i64::MAX as i128) as i64)
+            }
+            ArithmeticMode::Checked => {
+                i64::try_from(value).map_err(|_| EvalErrorKind::Overflow { operation })
+            }
+        }
+    }
+
+    /// Adds `r` to `l`.
+    pub(crate) fn add(self, l: i64, r: i64) -> Result<i64, EvalErrorKind> {
+        self.narrow("addition", l as i128 + r as i128)
+    }
+
+    /// Subtracts `r` from `l`.
+    pub(crate) fn sub(self, l: i64, r: i64) -> Result<i64, EvalErrorKind> {
+        self.narrow("subtraction", l as i128 - r as i128)
+    }
+
+    /// Multiplies `l` by `r`.
+    pub(crate) fn mul(self, l: i64, r: i64) -> Result<i64, EvalErrorKind> {
+        self.narrow("multiplication", l as i128 * r as i128)
+    }
+
+    /// Divides `l` by `r`, rounding toward zero.
+    pub(crate) fn div(self, l: i64, r: i64) -> Result<i64, EvalErrorKind> {
+        if r == 0 {
+            return Err(EvalErrorKind::DivisionByZero);
+        }
+        self.narrow("division", l as i128 / r as i128)
+    }
+
+    /// Divides `l` by `r`, rounding toward negative infinity.
+    pub(crate) fn div_floor(self, l: i64, r: i64) -> Result<i64, EvalErrorKind> {
+        if r == 0 {
+            return Err(EvalErrorKind::DivisionByZero);
+        }
+        let (l, r) = (l as i128, r as i128);
+        let quotient = l / r;
+        if l % r != 0 && (l < 0) != (r < 0) {
+            self.narrow("division", quotient - 1)
+        } else {
+            self.narrow("division", quotient)
+        }
+    }
+
+    /// Divides `l` by `r`, rounding toward positive infinity.
+    pub(crate) fn div_ceil(self, l: i64, r: i64) -> Result<i64, EvalErrorKind> {
+        if r == 0 {
+            return Err(EvalErrorKind::DivisionByZero);
+        }
+        let (l, r) = (l as i128, r as i128);
+        let quotient = l / r;
+        if l % r != 0 && (l < 0) == (r < 0) {
+            self.narrow("division", quotient + 1)
+        } else {
+            self.narrow("division", quotient)
+        }
+    }
+
+    /// Gets the remainder of dividing `l` by `r` with [`div`](Self::div). It never overflows.
+    pub(crate) fn rem(self, l: i64, r: i64) -> Result<i64, EvalErrorKind> {
+        if r == 0 {
+            return Err(EvalErrorKind::DivisionByZero);
+        }
+        Ok(l.wrapping_rem(r))
+    }
+
+    /// Raises `l` to the power of `r`. Negative exponents are rejected.
+    pub(crate) fn pow(self, l: i64, r: i64) -> Result<i64, EvalErrorKind> {
+        if r < 0 {
+            return Err(EvalErrorKind::NegativeExponent);
+        }
+
+        let exact = match l {
+            0 => Some(if r == 0 { 1 } else { 0 }),
+            1 => Some(1),
+            -1 => Some(if r % 2 == 0 { 1 } else { -1 }),
+            _ => u32::try_from(r).ok().and_then(|exp| l.checked_pow(exp)),
+        };
+
+        match (exact, self) {
+            (Some(result), _) => Ok(result),
+            (None, ArithmeticMode::Wrapping) => {
+                // exponentiation by squaring, the exponent may exceed `u32` so `i64::wrapping_pow`
+                // won't do
+                let (mut base, mut exp, mut result) = (l, r as u64, 1i64);
+                while exp > 0 {
+                    if exp & 1 == 1 {
+                        result = result.wrapping_mul(base);
+                    }
+                    base = base.wrapping_mul(base);
+                    exp >>= 1;
+                }
+                Ok(result)
+            }
+            (None, ArithmeticMode::Saturating) if l < 0 && r % 2 == 1 => Ok(i64::MIN),
+            (None, ArithmeticMode::Saturating) => Ok(i64::MAX),
+            (None, ArithmeticMode::Checked) => Err(EvalErrorKind::Overflow {
+                operation: "exponentiation",
+            }),
+        }
+    }
+
+    /// Negates `i`.
+    pub(crate) fn neg(self, i: i64) -> Result<i64, EvalErrorKind> {
+        self.narrow("negation", -(i as i128))
+    }
+
+    /// Gets the absolute value of `i`.
+    pub(crate) fn abs(self, i: i64) -> Result<i64, EvalErrorKind> {
+        self.narrow("abs", (i as i128).abs())
+    }
+
+    /// Sums up integers.
+    pub(crate) fn sum(self, iter: impl IntoIterator<Item = i64>) -> Result<i64, EvalErrorKind> {
+        // an `i128` can't overflow before summing up 2^64 integers
+        self.narrow("sum", iter.into_iter().map(i128::from).sum())
+    }
+}
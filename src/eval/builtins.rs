@@ -0,0 +1,349 @@
+//! The built-in function library available to all RollKit expressions.
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+use alloc::{string::ToString, vec::Vec};
+
+use super::{ArithmeticMode, EvalErrorKind, InnerValue, ListInner};
+
+/// A built-in function, callable by name from RollKit expressions.
+pub(super) struct Builtin {
+    /// The name of the function.
+    pub name: &'static str,
+    /// A human-readable description of the accepted arguments, used in error messages.
+    pub signature: &'static str,
+    /// The implementation of the function, taking the evaluated arguments and the arithmetic mode
+    /// of the evaluation.
+    pub func: fn(&Builtin, Vec<InnerValue>, ArithmeticMode) -> Result<InnerValue, EvalErrorKind>,
+}
+
+impl Builtin {
+    /// Creates the error reported when the function is called with wrong arguments.
+    fn wrong_arguments(&self) -> EvalErrorKind {
+        EvalErrorKind::WrongArguments {
+            name: self.name.to_string(),
+            expected: self.signature,
+        }
+    }
+
+    /// Creates the error reported when the function is applied to an empty list.
+    fn empty_list(&self) -> EvalErrorKind {
+        EvalErrorKind::EmptyList {
+            name: self.name.to_string(),
+        }
+    }
+
+    /// Extracts the only argument of a unary function.
+    fn single_arg(&self, args: Vec<InnerValue>) -> Result<InnerValue, EvalErrorKind> {
+        let [arg]: [InnerValue; 1] = args.try_into().map_err(|_| self.wrong_arguments())?;
+        Ok(arg)
+    }
+
+    /// Extracts the only argument of a unary function, which must be a list.
+    fn single_list_arg(&self, args: Vec<InnerValue>) -> Result<(bool, ListInner), EvalErrorKind> {
+        self.single_arg(args)?
+            .assert_list()
+            .map_err(|_| self.wrong_arguments())
+    }
+}
+
+/// All built-in functions, sorted by name.
+static BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "abs",
+        signature: "abs(value)",
+        func: builtin_abs,
+    },
+    Builtin {
+        name: "avg",
+        signature: "avg(list)",
+        func: builtin_avg,
+    },
+    Builtin {
+        name: "clamp",
+        signature: "clamp(value, low, high)",
+        func: builtin_clamp,
+    },
+    Builtin {
+        name: "count",
+        signature: "count(list) or count(list, value)",
+        func: builtin_count,
+    },
+    Builtin {
+        name: "len",
+        signature: "len(list)",
+        func: builtin_len,
+    },
+    Builtin {
+        name: "max",
+        signature: "max(list) or max(value, value, ...)",
+        func: builtin_max,
+    },
+    Builtin {
+        name: "min",
+        signature: "min(list) or min(value, value, ...)",
+        func: builtin_min,
+    },
+    Builtin {
+        name: "reverse",
+        signature: "reverse(list)",
+        func: builtin_reverse,
+    },
+    Builtin {
+        name: "sort",
+        signature: "sort(list)",
+        func: builtin_sort,
+    },
+    Builtin {
+        name: "sum",
+        signature: "sum(value)",
+        func: builtin_sum,
+    },
+];
+
+/// Looks up a built-in function by name.
+pub(super) fn lookup(name: &str) -> Option<&'static Builtin> {
+    BUILTINS
+        .binary_search_by(|builtin| builtin.name.cmp(name))
+        .ok()
+        .map(|index| &BUILTINS[index])
+}
+
+/// Finds the extreme element of a list, or among several integer arguments, according to `pick`.
+fn extreme(
+    builtin: &Builtin,
+    args: Vec<InnerValue>,
+    arith: ArithmeticMode,
+    pick: fn(i64, i64) -> i64,
+) -> Result<InnerValue, EvalErrorKind> {
+    let values = match args.len() {
+        0 => return Err(builtin.wrong_arguments()),
+        1 => builtin.single_arg(args)?.into_vec(),
+        _ => args
+            .into_iter()
+            .map(|arg| arg.try_into_integer(arith))
+            .collect::<Result<Result<Vec<_>, _>, _>>()?
+            .map_err(|_| builtin.wrong_arguments())?,
+    };
+
+    values
+        .into_iter()
+        .reduce(pick)
+        .map(InnerValue::Integer)
+        .ok_or_else(|| builtin.empty_list())
+}
+
+fn builtin_abs(
+    builtin: &Builtin,
+    args: Vec<InnerValue>,
+    arith: ArithmeticMode,
+) -> Result<InnerValue, EvalErrorKind> {
+    builtin
+        .single_arg(args)?
+        .map_elementwise(arith, |i| arith.abs(i))
+}
+
+fn builtin_avg(
+    builtin: &Builtin,
+    args: Vec<InnerValue>,
+    _: ArithmeticMode,
+) -> Result<InnerValue, EvalErrorKind> {
+    let (_, list) = builtin.single_list_arg(args)?;
+    let vec = list.into_vec();
+    if vec.is_empty() {
+        return Err(builtin.empty_list());
+    }
+
+    let sum: i128 = vec.iter().map(|&i| i as i128).sum();
+    Ok(InnerValue::Integer((sum / vec.len() as i128) as i64))
+}
+
+fn builtin_clamp(
+    builtin: &Builtin,
+    args: Vec<InnerValue>,
+    arith: ArithmeticMode,
+) -> Result<InnerValue, EvalErrorKind> {
+    let [value, low, high]: [InnerValue; 3] =
+        args.try_into().map_err(|_| builtin.wrong_arguments())?;
+    let (low, high) = match (low.try_into_integer(arith)?, high.try_into_integer(arith)?) {
+        (Ok(low), Ok(high)) if low <= high => (low, high),
+        _ => return Err(builtin.wrong_arguments()),
+    };
+
+    value.map_elementwise(arith, |i| Ok(i.clamp(low, high)))
+}
+
+fn builtin_count(
+    builtin: &Builtin,
+    args: Vec<InnerValue>,
+    arith: ArithmeticMode,
+) -> Result<InnerValue, EvalErrorKind> {
+    let mut args = args.into_iter();
+    let (_, list) = match args.next() {
+        Some(list) => list.assert_list().map_err(|_| builtin.wrong_arguments())?,
+        None => return Err(builtin.wrong_arguments()),
+    };
+    let target = match args.next() {
+        Some(target) => Some(
+            target
+                .try_into_integer(arith)?
+                .map_err(|_| builtin.wrong_arguments())?,
+        ),
+        None => None,
+    };
+    if args.next().is_some() {
+        return Err(builtin.wrong_arguments());
+    }
+
+    let vec = list.into_vec();
+    let count = match target {
+        Some(target) => vec.iter().filter(|&&i| i == target).count(),
+        None => vec.iter().filter(|&&i| i != 0).count(),
+    };
+    Ok(InnerValue::Integer(count as i64))
+}
+
+fn builtin_len(
+    builtin: &Builtin,
+    args: Vec<InnerValue>,
+    _: ArithmeticMode,
+) -> Result<InnerValue, EvalErrorKind> {
+    let (_, list) = builtin.single_list_arg(args)?;
+    Ok(InnerValue::Integer(list.len() as i64))
+}
+
+fn builtin_max(
+    builtin: &Builtin,
+    args: Vec<InnerValue>,
+    arith: ArithmeticMode,
+) -> Result<InnerValue, EvalErrorKind> {
+    extreme(builtin, args, arith, i64::max)
+}
+
+fn builtin_min(
+    builtin: &Builtin,
+    args: Vec<InnerValue>,
+    arith: ArithmeticMode,
+) -> Result<InnerValue, EvalErrorKind> {
+    extreme(builtin, args, arith, i64::min)
+}
+
+fn builtin_reverse(
+    builtin: &Builtin,
+    args: Vec<InnerValue>,
+    _: ArithmeticMode,
+) -> Result<InnerValue, EvalErrorKind> {
+    let (strong, list) = builtin.single_list_arg(args)?;
+    let mut vec = list.into_vec();
+    vec.reverse();
+    Ok(InnerValue::List {
+        strong,
+        inner: ListInner::List(vec),
+    })
+}
+
+fn builtin_sort(
+    builtin: &Builtin,
+    args: Vec<InnerValue>,
+    _: ArithmeticMode,
+) -> Result<InnerValue, EvalErrorKind> {
+    let (strong, list) = builtin.single_list_arg(args)?;
+    let mut vec = list.into_vec();
+    vec.sort_unstable();
+    Ok(InnerValue::List {
+        strong,
+        inner: ListInner::List(vec),
+    })
+}
+
+fn builtin_sum(
+    builtin: &Builtin,
+    args: Vec<InnerValue>,
+    arith: ArithmeticMode,
+) -> Result<InnerValue, EvalErrorKind> {
+    Ok(InnerValue::Integer(match builtin.single_arg(args)? {
+        InnerValue::Integer(i) => i,
+        InnerValue::List { inner, .. } => inner.sum(arith)?,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::{EvalError, Value, eval_with, parse};
+
+    fn eval_str(input: &str) -> Result<Value, EvalErrorKind> {
+        eval_with(&parse(input).unwrap(), &mut StdRng::seed_from_u64(0))
+            .map_err(EvalError::into_kind)
+    }
+
+    #[test]
+    fn test_builtins_sorted() {
+        assert!(BUILTINS.windows(2).all(|w| w[0].name < w[1].name));
+    }
+
+    #[test]
+    fn test_builtin_calls() {
+        let cases = [
+            ("sum({1, 2, 3})", Value::Integer(6)),
+            ("sum(4)", Value::Integer(4)),
+            ("len([1, 10, 3])", Value::Integer(4)),
+            ("max({3, 9, 4})", Value::Integer(9)),
+            ("max(3, {4, 5}, 7)", Value::Integer(9)),
+            ("min([5, 1, 2])", Value::Integer(1)),
+            ("abs(-5)", Value::Integer(5)),
+            ("abs({{-1, 2, -3}})", Value::List(vec![1, 2, 3])),
+            ("sort({3, 1, 2})", Value::List(vec![1, 2, 3])),
+            ("reverse([1, 3])", Value::List(vec![3, 2, 1])),
+            ("count({0, 1, 2, 0})", Value::Integer(2)),
+            ("count({1, 6, 6, 3}, 6)", Value::Integer(2)),
+            ("avg({1, 2, 4})", Value::Integer(2)),
+            ("clamp(12, 1, 10)", Value::Integer(10)),
+            ("clamp({{-2, 5, 20}}, 1, 10)", Value::List(vec![1, 5, 10])),
+            ("len(10d6)", Value::Integer(10)),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_str(input), Ok(expected), "Input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_builtin_errors() {
+        let wrong_arguments = |name: &str| {
+            let expected = lookup(name).unwrap().signature;
+            EvalErrorKind::WrongArguments {
+                name: name.to_string(),
+                expected,
+            }
+        };
+
+        let cases = [
+            (
+                "frobnicate(1)",
+                EvalErrorKind::UnknownFunction {
+                    name: "frobnicate".to_string(),
+                },
+            ),
+            ("len(5)", wrong_arguments("len")),
+            ("sum(1, 2)", wrong_arguments("sum")),
+            ("max()", wrong_arguments("max")),
+            ("max(1, {{1, 2}})", wrong_arguments("max")),
+            ("clamp(5, 10, 1)", wrong_arguments("clamp")),
+            ("count({1}, 1, 2)", wrong_arguments("count")),
+            (
+                "avg(0d6)",
+                EvalErrorKind::EmptyList {
+                    name: "avg".to_string(),
+                },
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_str(input), Err(expected), "Input: {}", input);
+        }
+    }
+}
@@ -0,0 +1,515 @@
+//! Rolling dice, with dice modifiers applied.
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use core::ops::RangeInclusive;
+
+use rand::{Rng, seq::IndexedRandom};
+
+use super::{DieRoll, EvalErrorKind, EvalVisitor, InnerValue, ListInner};
+use crate::ast::{CompareOp, ComparePoint, DiceModifier, ExplodeKind, RangeLiteral};
+
+/// A die, which can be rolled repeatedly to get one of its faces uniformly at random.
+pub(super) enum Die {
+    /// A die with an explicit, non-empty list of faces.
+    List(Vec<i64>),
+    /// A die with a contiguous range of faces.
+    Range(RangeInclusive<i64>),
+}
+
+impl Die {
+    /// Creates a die from the evaluated sides of a dice roll, either a positive integer `n` for
+    /// faces `1..=n`, or a non-empty list of faces. Lists of consecutive ascending faces, e.g.,
+    /// `{1, 2, 3, 4, 5, 6}`, roll exactly like the equivalent ranges.
+    pub fn new(sides: InnerValue) -> Result<Self, EvalErrorKind> {
+        let faces = match sides {
+            InnerValue::Integer(n) if n < 1 => {
+                return Err(EvalErrorKind::InvalidSides { sides: n });
+            }
+            InnerValue::Integer(n) => ListInner::Range(RangeLiteral {
+                start: 1,
+                end: n,
+                step: None,
+            }),
+            InnerValue::List { inner, .. } => inner,
+        };
+
+        Ok(match faces {
+            ListInner::List(lst) if lst.is_empty() => return Err(EvalErrorKind::EmptyDie),
+            ListInner::List(lst) if is_consecutive(&lst) => Die::Range(lst[0]..=lst[lst.len() - 1]),
+            ListInner::List(lst) => Die::List(lst),
+            ListInner::Range(RangeLiteral { start, end, step })
+                if step.is_none_or(|step| step.wrapping_abs() == 1) =>
+            {
+                if end >= start {
+                    Die::Range(start..=end)
+                } else {
+                    Die::Range(end..=start)
+                }
+            }
+            faces => Die::List(faces.into_vec()),
+        })
+    }
+
+    /// Rolls the die once.
+    pub fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> i64 {
+        match self {
+            Die::List(lst) => *lst.choose(rng).unwrap(),
+            Die::Range(range) => rng.random_range(range.clone()),
+        }
+    }
+
+    /// Returns `true` if at least one face of the die matches none of the conditions.
+    pub fn any_face_matches_none(&self, conditions: &[ComparePoint]) -> bool {
+        let matches_none = |face: i64| !conditions.iter().any(|c| c.matches(face));
+        match self {
+            Die::List(lst) => lst.iter().any(|&face| matches_none(face)),
+            Die::Range(range) => {
+                // The faces matching none of the conditions form intervals, whose bounds are
+                // either bounds of the range, or next to the values of the conditions.
+                let (start, end) = (*range.start(), *range.end());
+                let candidates = conditions.iter().flat_map(|c| {
+                    [
+                        c.value.saturating_sub(1),
+                        c.value,
+                        c.value.saturating_add(1),
+                    ]
+                });
+                [start, end]
+                    .into_iter()
+                    .chain(candidates)
+                    .filter(|face| range.contains(face))
+                    .any(matches_none)
+            }
+        }
+    }
+
+    /// Returns the highest face of the die.
+    pub fn max_face(&self) -> i64 {
+        match self {
+            Die::List(lst) => lst.iter().copied().max().unwrap_or_default(),
+            Die::Range(range) => *range.end(),
+        }
+    }
+}
+
+/// The dice modifiers of a dice roll, grouped by kind. Conditions given by multiple modifiers of
+/// the same kind are combined, i.e., `3d6!=1!=6` explodes on both 1 and 6. If explode modifiers of
+/// different [kinds](ExplodeKind) are given, the last one decides the kind.
+pub(crate) struct Modifiers {
+    /// The kind of explosion.
+    pub explode_kind: ExplodeKind,
+    /// The conditions on which dice explode.
+    pub explode: Vec<ComparePoint>,
+    /// The conditions on which dice are rerolled until they no longer match.
+    pub reroll: Vec<ComparePoint>,
+    /// The conditions on which dice are rerolled at most once.
+    pub reroll_once: Vec<ComparePoint>,
+    /// The conditions for a die to count as a success. If empty, successes are not counted.
+    pub success: Vec<ComparePoint>,
+    /// The conditions for a die to count as a failure.
+    pub failure: Vec<ComparePoint>,
+}
+
+impl Modifiers {
+    /// Groups the modifiers of a dice roll whose die has `max_face` as its highest face.
+    pub fn new(modifiers: &[DiceModifier], max_face: i64) -> Self {
+        let mut result = Modifiers {
+            explode_kind: ExplodeKind::Standard,
+            explode: Vec::new(),
+            reroll: Vec::new(),
+            reroll_once: Vec::new(),
+            success: Vec::new(),
+            failure: Vec::new(),
+        };
+
+        for modifier in modifiers {
+            match modifier {
+                DiceModifier::Explode { kind, condition } => {
+                    result.explode_kind = *kind;
+                    result.explode.push(condition.unwrap_or(ComparePoint {
+                        op: CompareOp::Equal,
+                        value: max_face,
+                    }))
+                }
+                DiceModifier::Reroll {
+                    once: false,
+                    condition,
+                } => result.reroll.push(*condition),
+                DiceModifier::Reroll {
+                    once: true,
+                    condition,
+                } => result.reroll_once.push(*condition),
+                DiceModifier::CountSuccesses { success, failure } => {
+                    result.success.push(*success);
+                    result.failure.extend(failure);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Rolls the die once, rerolling as requested by the reroll modifiers. Each roll counts as a
+    /// draw against the limits of the evaluation, and rolls being rerolled are recorded if tracing.
+    /// `extra` tells whether the roll is an extra roll from an explosion.
+    fn roll<R: Rng + ?Sized>(
+        &self,
+        die: &Die,
+        visitor: &mut EvalVisitor<'_, R>,
+        extra: bool,
+    ) -> Result<i64, EvalErrorKind> {
+        let context = visitor.context;
+        visitor.usage.draw()?;
+        let mut face = die.roll(visitor.rng);
+        let mut rerolls = 0;
+        let mut rerolled_once = false;
+
+        loop {
+            if self.reroll.iter().any(|c| c.matches(face)) {
+                if rerolls >= context.reroll_limit {
+                    return Err(EvalErrorKind::RerollLimitExceeded {
+                        limit: context.reroll_limit,
+                    });
+                }
+                rerolls += 1;
+            } else if !rerolled_once && self.reroll_once.iter().any(|c| c.matches(face)) {
+                rerolled_once = true;
+            } else {
+                return Ok(face);
+            }
+
+            if let Some(tracer) = &mut visitor.tracer {
+                tracer.roll(DieRoll {
+                    face,
+                    value: face,
+                    rerolled: true,
+                    exploded: false,
+                    extra,
+                });
+            }
+            visitor.usage.draw()?;
+            face = die.roll(visitor.rng);
+        }
+    }
+
+    /// Returns `true` if the die explodes on the face.
+    pub fn explodes(&self, face: i64) -> bool {
+        self.explode.iter().any(|condition| condition.matches(face))
+    }
+
+    /// Returns the score of a die when counting successes, i.e., 1 for a success, -1 for a
+    /// failure, and 0 otherwise.
+    pub fn score(&self, face: i64) -> i64 {
+        let matches = |conditions: &[ComparePoint]| conditions.iter().any(|c| c.matches(face));
+        matches(&self.success) as i64 - matches(&self.failure) as i64
+    }
+}
+
+/// Returns `true` if the list is a run of consecutive ascending integers, e.g., `[3, 4, 5]`.
+pub(super) fn is_consecutive(lst: &[i64]) -> bool {
+    lst.windows(2).all(|w| w[0].checked_add(1) == Some(w[1]))
+}
+
+/// A die with its modifiers grouped, checked to be rollable.
+pub(super) struct PreparedDie {
+    die: Die,
+    modifiers: Modifiers,
+}
+
+impl PreparedDie {
+    /// Prepares a die from the evaluated sides of a dice roll and its modifiers.
+    pub fn new(sides: InnerValue, modifiers: &[DiceModifier]) -> Result<Self, EvalErrorKind> {
+        let die = Die::new(sides)?;
+        let modifiers = Modifiers::new(modifiers, die.max_face());
+        if !die.any_face_matches_none(&modifiers.reroll) {
+            return Err(EvalErrorKind::UnsatisfiableReroll);
+        }
+        Ok(Self { die, modifiers })
+    }
+}
+
+/// Checks the evaluated number of dice of a dice roll.
+pub(super) fn check_count<R: Rng + ?Sized>(
+    visitor: &EvalVisitor<'_, R>,
+    count: InnerValue,
+) -> Result<i64, EvalErrorKind> {
+    let count = count.assert_integer()?;
+    if count < 0 {
+        return Err(EvalErrorKind::NegativeDiceCount { count });
+    }
+    visitor.usage.check_dice(count)?;
+    Ok(count)
+}
+
+/// Rolls `count` dice with the given `sides`, applying the modifiers.
+///
+/// Each roll of a die, including the extra rolls of exploding dice, is first rerolled as requested
+/// by the reroll modifiers, then checked for explosion. If successes are counted, the result is the
+/// net number of successes, otherwise it's the list of all dice.
+pub(super) fn roll_dice<R: Rng + ?Sized>(
+    visitor: &mut EvalVisitor<'_, R>,
+    count: InnerValue,
+    sides: InnerValue,
+    modifiers: &[DiceModifier],
+) -> Result<InnerValue, EvalErrorKind> {
+    let count = check_count(visitor, count)?;
+    let die = PreparedDie::new(sides, modifiers)?;
+    roll_prepared(visitor, count, &die)
+}
+
+/// Rolls `count` dice of a prepared die, like [`roll_dice`]. The count must be checked with
+/// [`check_count`].
+pub(super) fn roll_prepared<R: Rng + ?Sized>(
+    visitor: &mut EvalVisitor<'_, R>,
+    count: i64,
+    die: &PreparedDie,
+) -> Result<InnerValue, EvalErrorKind> {
+    let context = visitor.context;
+    let PreparedDie { die, modifiers } = die;
+
+    let mut results = Vec::new();
+    for _ in 0..count {
+        let mut face = modifiers.roll(die, visitor, false)?;
+        let mut value = face;
+        results.push(face);
+
+        let mut explosions = 0;
+        loop {
+            let exploded = modifiers.explodes(face) && explosions < context.explode_limit;
+            if let Some(tracer) = &mut visitor.tracer {
+                tracer.roll(DieRoll {
+                    face,
+                    value,
+                    rerolled: false,
+                    exploded,
+                    extra: explosions > 0,
+                });
+            }
+            if !exploded {
+                break;
+            }
+
+            face = modifiers.roll(die, visitor, true)?;
+            value = face;
+            explosions += 1;
+
+            match modifiers.explode_kind {
+                ExplodeKind::Standard => results.push(face),
+                ExplodeKind::Compounding => {
+                    let total = results.last_mut().unwrap();
+                    *total = context.arithmetic.add(*total, face)?;
+                }
+                ExplodeKind::Penetrating => {
+                    value = context.arithmetic.sub(face, 1)?;
+                    results.push(value);
+                }
+            }
+        }
+    }
+
+    if modifiers.success.is_empty() {
+        return Ok(InnerValue::List {
+            strong: false,
+            inner: ListInner::List(results),
+        });
+    }
+
+    Ok(InnerValue::Integer(
+        results.iter().map(|&face| modifiers.score(face)).sum(),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::{EvalContext, EvalError, Value, eval_with, eval_with_context, parse};
+
+    fn roll_str(input: &str, context: &EvalContext) -> Vec<i64> {
+        let expr = parse(input).unwrap();
+        let mut rng = StdRng::seed_from_u64(42);
+        (0..50)
+            .flat_map(
+                |_| match eval_with_context(&expr, &mut rng, context).unwrap() {
+                    Value::List(lst) => lst,
+                    value => panic!("Expected a list, got {:?}", value),
+                },
+            )
+            .collect()
+    }
+
+    #[test]
+    fn test_explode() {
+        let context = EvalContext::default();
+
+        // each exploding die adds exactly one more die
+        let rolls = roll_str("5d6!", &context);
+        let sixes = rolls.iter().filter(|&&face| face == 6).count();
+        assert_eq!(rolls.len(), 50 * 5 + sixes);
+
+        let rolls = roll_str("3d6!>=5", &context);
+        let explosions = rolls.iter().filter(|&&face| face >= 5).count();
+        assert_eq!(rolls.len(), 50 * 3 + explosions);
+
+        // the highest face of a list die
+        let rolls = roll_str("2d{4, 2}!", &context);
+        let fours = rolls.iter().filter(|&&face| face == 4).count();
+        assert_eq!(rolls.len(), 50 * 2 + fours);
+
+        // compounding explosions keep the number of dice, and each die is the sum of its rolls
+        let rolls = roll_str("4d6!!", &context);
+        assert_eq!(rolls.len(), 50 * 4);
+        assert!(rolls.iter().all(|&face| face % 6 != 0));
+        assert!(rolls.iter().any(|&face| face > 6));
+
+        // penetrating explosions subtract 1 from each extra roll, but still explode on the
+        // unmodified roll
+        let rolls = roll_str("3d6!p", &context);
+        let mut extra = false;
+        for face in rolls {
+            if extra {
+                assert!((0..=5).contains(&face));
+                extra = face == 5;
+            } else {
+                assert!((1..=6).contains(&face));
+                extra = face == 6;
+            }
+        }
+        let context = EvalContext {
+            explode_limit: 2,
+            ..Default::default()
+        };
+        assert_eq!(roll_str("2d1!p", &context), [1, 0, 0].repeat(100));
+
+        // dice that always explode stop at the limit
+        let context = EvalContext {
+            explode_limit: 10,
+            ..Default::default()
+        };
+        assert_eq!(roll_str("1d1!", &context), vec![1; 50 * 11]);
+        assert_eq!(roll_str("1d1!!", &context), vec![11; 50]);
+        assert_eq!(
+            roll_str("1d1!p", &context),
+            [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0].repeat(50)
+        );
+    }
+
+    #[test]
+    fn test_reroll() {
+        let context = EvalContext::default();
+
+        let rolls = roll_str("4d6r<3", &context);
+        assert_eq!(rolls.len(), 50 * 4);
+        assert!(rolls.iter().all(|&face| face >= 3));
+
+        let rolls = roll_str("4d6r1r=2r6", &context);
+        assert!(rolls.iter().all(|&face| (3..=5).contains(&face)));
+
+        // rerolled once, so low faces are still possible but less likely
+        let rolls = roll_str("10d6ro<=2", &context);
+        let low = rolls.iter().filter(|&&face| face <= 2).count();
+        assert!(low > 0 && low < 50 * 10 / 3);
+
+        // rerolls also apply to the extra rolls of exploding dice
+        let rolls = roll_str("3d6r1!", &context);
+        assert!(!rolls.contains(&1));
+
+        let eval_str = |input: &str| {
+            eval_with_context(
+                &parse(input).unwrap(),
+                &mut StdRng::seed_from_u64(42),
+                &context,
+            )
+            .map_err(EvalError::into_kind)
+        };
+        assert_eq!(eval_str("1d6r<7"), Err(EvalErrorKind::UnsatisfiableReroll));
+        assert_eq!(
+            eval_str("1d{1, 2, 3}r1r2r3"),
+            Err(EvalErrorKind::UnsatisfiableReroll)
+        );
+        assert_eq!(
+            eval_str("1d1000000000000r<10r>=10"),
+            Err(EvalErrorKind::UnsatisfiableReroll)
+        );
+        assert_eq!(
+            eval_str("1d1000000000000r<10r>10"),
+            Err(EvalErrorKind::RerollLimitExceeded { limit: 100 })
+        );
+        assert_eq!(
+            eval_str("1d[1, 1000]r<1000"),
+            Err(EvalErrorKind::RerollLimitExceeded { limit: 100 })
+        );
+        assert!(eval_str("1d[1, 1000]ro<1000").is_ok());
+    }
+
+    #[test]
+    fn test_count_successes() {
+        let context = EvalContext::default();
+        let eval_str = |input: &str| {
+            eval_with_context(
+                &parse(input).unwrap(),
+                &mut StdRng::seed_from_u64(42),
+                &context,
+            )
+            .map_err(EvalError::into_kind)
+        };
+
+        // the same rolls, counted in different ways
+        let rolls = roll_str("10d10", &context);
+        let count = |pred: fn(i64) -> bool| rolls.iter().filter(|&&face| pred(face)).count();
+        let expected = count(|face| face >= 8) as i64 - count(|face| face == 1) as i64;
+        let mut rng = StdRng::seed_from_u64(42);
+        let expr = parse("10d10>=8f1").unwrap();
+        let total: i64 = (0..50)
+            .map(|_| eval_with_context(&expr, &mut rng, &context).unwrap().sum())
+            .sum();
+        assert_eq!(total, expected);
+
+        assert_eq!(eval_str("5d1>=1"), Ok(Value::Integer(5)));
+        assert_eq!(eval_str("5d1=1f1"), Ok(Value::Integer(0)));
+        assert_eq!(eval_str("5d1>1f<=1"), Ok(Value::Integer(-5)));
+        assert_eq!(eval_str("2d{3, 4}>=3 + 1"), Ok(Value::Integer(3)));
+        assert_eq!(eval_str("3d1!!1>=3"), Ok(Value::Integer(3)));
+        assert_eq!(eval_str("3d6>=4kh1"), Err(EvalErrorKind::ListExpected));
+    }
+
+    #[test]
+    fn test_invalid_dice() {
+        let cases = [
+            ("1d0", EvalErrorKind::InvalidSides { sides: 0 }),
+            ("2d(-6)", EvalErrorKind::InvalidSides { sides: -6 }),
+            ("0d0", EvalErrorKind::InvalidSides { sides: 0 }),
+            ("1d(0d6)", EvalErrorKind::EmptyDie),
+            ("1d(0d6)!", EvalErrorKind::EmptyDie),
+            ("(-3)d6", EvalErrorKind::NegativeDiceCount { count: -3 }),
+            (
+                "(1 - 4)d6r1",
+                EvalErrorKind::NegativeDiceCount { count: -3 },
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let result = eval_with(&parse(input).unwrap(), &mut StdRng::seed_from_u64(0));
+            assert_eq!(
+                result.map_err(EvalError::into_kind),
+                Err(expected),
+                "Input: {}",
+                input
+            );
+        }
+
+        assert_eq!(
+            eval_with(&parse("0d6").unwrap(), &mut StdRng::seed_from_u64(0)),
+            Ok(Value::List(vec![]))
+        );
+        assert_eq!(
+            eval_with(&parse("-3d6 < 0").unwrap(), &mut StdRng::seed_from_u64(0)),
+            Ok(Value::Integer(1))
+        );
+    }
+}
@@ -0,0 +1,253 @@
+//! User-registered functions, which extend the language with application-specific functions.
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+
+use core::fmt;
+
+use rand::RngCore;
+
+use super::{EvalError, Value};
+use crate::ast::Expr;
+
+/// A function taking evaluated arguments.
+type EagerFunction = dyn Fn(&[Value]) -> Result<Value, EvalError> + Send + Sync;
+/// A function taking unevaluated arguments.
+type LazyFunction = dyn Fn(&[Expr], &mut CallContext<'_>) -> Result<Value, EvalError> + Send + Sync;
+
+/// A function registered in a [`FunctionRegistry`].
+enum UserFunction {
+    Eager(Box<EagerFunction>),
+    Lazy(Box<LazyFunction>),
+}
+
+/// A registry of user-defined functions, which can be called from RollKit expressions just like
+/// the built-in ones.
+///
+/// Functions are registered either with [`register`](FunctionRegistry::register), receiving the
+/// evaluated arguments, or with [`register_lazy`](FunctionRegistry::register_lazy), receiving the
+/// argument expressions and a [`CallContext`] to evaluate them and roll dice on demand. A
+/// registered function shadows a built-in function with the same name.
+///
+/// # Example
+///
+/// ```
+/// # use rand::{SeedableRng, rngs::StdRng};
+/// # use rollkit::{EvalContext, EvalErrorKind, FunctionRegistry, Value, eval_with_context, parse};
+/// let mut functions = FunctionRegistry::new();
+/// functions.register("wound_penalty", |args| match args {
+///     [wounds] => Ok(Value::Integer(-wounds.sum().min(3))),
+///     _ => Err(EvalErrorKind::WrongArguments {
+///         name: "wound_penalty".into(),
+///         expected: "wound_penalty(wounds)",
+///     }
+///     .into()),
+/// });
+/// let context = EvalContext { functions, ..Default::default() };
+///
+/// let expr = parse("wound_penalty(2)").unwrap();
+/// let result = eval_with_context(&expr, &mut StdRng::from_os_rng(), &context).unwrap();
+/// assert_eq!(result, Value::Integer(-2));
+/// ```
+#[derive(Default)]
+pub struct FunctionRegistry {
+    functions: BTreeMap<String, UserFunction>,
+}
+
+impl FunctionRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a function receiving its evaluated arguments. Replaces any function previously
+    /// registered with the same name.
+    pub fn register<F>(&mut self, name: impl Into<String>, function: F) -> &mut Self
+    where
+        F: Fn(&[Value]) -> Result<Value, EvalError> + Send + Sync + 'static,
+    {
+        self.functions
+            .insert(name.into(), UserFunction::Eager(Box::new(function)));
+        self
+    }
+
+    /// Registers a function receiving its arguments unevaluated. The function can evaluate them,
+    /// any number of times, and draw random numbers through the provided [`CallContext`]. Replaces
+    /// any function previously registered with the same name.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rand::Rng;
+    /// # use rollkit::{EvalContext, FunctionRegistry, Value, eval_with_context, parse};
+    /// let mut functions = FunctionRegistry::new();
+    /// // evaluates its argument twice and keeps the better result
+    /// functions.register_lazy("twice", |args, ctx| {
+    ///     let first = ctx.eval(&args[0])?.sum();
+    ///     let second = ctx.eval(&args[0])?.sum();
+    ///     Ok(Value::Integer(first.max(second)))
+    /// });
+    /// // uses the context as a random number generator
+    /// functions.register_lazy("coin", |_, ctx| Ok(Value::Integer(ctx.random_range(0..=1))));
+    /// let context = EvalContext { functions, ..Default::default() };
+    ///
+    /// let expr = parse("twice(3d6) + coin()").unwrap();
+    /// let result = eval_with_context(&expr, &mut rand::rng(), &context).unwrap();
+    /// assert!((3..=19).contains(&result.sum()));
+    /// ```
+    pub fn register_lazy<F>(&mut self, name: impl Into<String>, function: F) -> &mut Self
+    where
+        F: Fn(&[Expr], &mut CallContext<'_>) -> Result<Value, EvalError> + Send + Sync + 'static,
+    {
+        self.functions
+            .insert(name.into(), UserFunction::Lazy(Box::new(function)));
+        self
+    }
+
+    /// Returns `true` if a function with the given name is registered.
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Removes the function with the given name, returning `true` if it was registered.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.functions.remove(name).is_some()
+    }
+
+    /// Returns an iterator over the names of all registered functions, in sorted order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.functions.keys().map(String::as_str)
+    }
+
+    /// Calls the function with the given name, or returns `None` if it's not registered.
+    pub(super) fn call(
+        &self,
+        name: &str,
+        args: &[Expr],
+        evaluator: &mut dyn ArgEvaluator,
+    ) -> Option<Result<Value, EvalError>> {
+        let function = self.functions.get(name)?;
+        Some(match function {
+            UserFunction::Eager(function) => args
+                .iter()
+                .map(|arg| evaluator.eval_arg(arg))
+                .collect::<Result<Vec<_>, _>>()
+                .and_then(|args| function(&args)),
+            UserFunction::Lazy(function) => function(args, &mut CallContext { evaluator }),
+        })
+    }
+}
+
+impl fmt::Debug for FunctionRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+/// The interface of the evaluator exposed to lazy functions through [`CallContext`].
+pub(super) trait ArgEvaluator {
+    /// Evaluates an argument expression.
+    fn eval_arg(&mut self, expr: &Expr) -> Result<Value, EvalError>;
+    /// Forwards to [`RngCore::next_u32`] of the random number generator used by the evaluation.
+    fn next_u32(&mut self) -> u32;
+    /// Forwards to [`RngCore::next_u64`] of the random number generator used by the evaluation.
+    fn next_u64(&mut self) -> u64;
+    /// Forwards to [`RngCore::fill_bytes`] of the random number generator used by the evaluation.
+    fn fill_bytes(&mut self, dst: &mut [u8]);
+}
+
+/// The handle passed to [lazy functions](FunctionRegistry::register_lazy), to evaluate argument
+/// expressions and draw random numbers.
+///
+/// It implements [`RngCore`], forwarding to the random number generator of the ongoing evaluation,
+/// so all methods of [`rand::Rng`] can be used on it directly.
+pub struct CallContext<'c> {
+    evaluator: &'c mut dyn ArgEvaluator,
+}
+
+impl CallContext<'_> {
+    /// Evaluates an expression in the ongoing evaluation, with the same random number generator
+    /// and context.
+    pub fn eval(&mut self, expr: &Expr) -> Result<Value, EvalError> {
+        self.evaluator.eval_arg(expr)
+    }
+}
+
+impl RngCore for CallContext<'_> {
+    fn next_u32(&mut self) -> u32 {
+        self.evaluator.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.evaluator.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dst: &mut [u8]) {
+        self.evaluator.fill_bytes(dst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::{EvalContext, EvalErrorKind, eval_with_context, parse, parsing::Span};
+
+    fn eval_str(input: &str, context: &EvalContext) -> Result<Value, EvalError> {
+        eval_with_context(
+            &parse(input).unwrap(),
+            &mut StdRng::seed_from_u64(0),
+            context,
+        )
+    }
+
+    #[test]
+    fn test_user_functions() {
+        let mut functions = FunctionRegistry::new();
+        functions
+            .register("double", |args| {
+                Ok(Value::List(args.iter().map(|arg| arg.sum() * 2).collect()))
+            })
+            .register("max", |_| Ok(Value::Integer(42)))
+            .register_lazy("arity", |args, _| Ok(Value::Integer(args.len() as i64)))
+            .register_lazy("first", |args, ctx| ctx.eval(&args[0]));
+        let context = EvalContext {
+            functions,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            eval_str("double(1, {2, 3})", &context),
+            Ok(Value::List(vec![2, 10]))
+        );
+        assert_eq!(
+            eval_str("double(1, 2) + 1", &context),
+            Ok(Value::Integer(7))
+        );
+        assert_eq!(eval_str("max({1, 2})", &context), Ok(Value::Integer(42)));
+        assert_eq!(eval_str("min({1, 2})", &context), Ok(Value::Integer(1)));
+        // arguments of lazy functions are not evaluated unless requested
+        assert_eq!(
+            eval_str("arity(1d6, nope())", &context),
+            Ok(Value::Integer(2))
+        );
+        assert_eq!(
+            eval_str("first(sum([1, 4]), nope())", &context),
+            Ok(Value::Integer(10))
+        );
+        assert_eq!(
+            eval_str("double(nope())", &context),
+            Err(EvalError::new(
+                EvalErrorKind::UnknownFunction {
+                    name: "nope".into()
+                },
+                Some(Span::new(7, 13))
+            ))
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! Limits on the resources used by an evaluation.
+
+use super::EvalErrorKind;
+
+/// Limits on the resources an evaluation may use, to keep untrusted expressions like
+/// `1000000000000d6` from exhausting memory or time. Exceeding a limit fails the evaluation with a
+/// dedicated [`EvalErrorKind`].
+///
+/// # Example
+///
+/// ```
+/// # use rand::{SeedableRng, rngs::StdRng};
+/// # use rollkit::{EvalContext, EvalErrorKind, EvalLimits, eval_with_context, parse};
+/// let limits = EvalLimits { max_dice: 100, ..Default::default() };
+/// let context = EvalContext { limits, ..Default::default() };
+///
+/// let expr = parse("1000d6").unwrap();
+/// let error = eval_with_context(&expr, &mut StdRng::from_os_rng(), &context).unwrap_err();
+/// assert_eq!(error.kind(), &EvalErrorKind::TooManyDice { limit: 100, requested: 1000 });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvalLimits {
+    /// The maximum number of dice a single dice roll can roll, not counting extra dice from
+    /// explosions. Defaults to 10,000.
+    pub max_dice: usize,
+    /// The maximum length of any list produced during the evaluation, including range literals.
+    /// Defaults to 100,000.
+    pub max_list_len: usize,
+    /// The maximum number of random draws, i.e., rolls of a single die, including rerolls and
+    /// explosions. Defaults to 1,000,000.
+    pub max_draws: usize,
+    /// The maximum nesting depth of expressions being evaluated. Defaults to 256.
+    pub max_depth: usize,
+    /// The maximum number of expression nodes evaluated, counting each evaluation of a node
+    /// separately, e.g., when a function evaluates an argument multiple times. Defaults to
+    /// 1,000,000.
+    pub max_steps: usize,
+}
+
+impl EvalLimits {
+    /// Limits that never stop an evaluation.
+    pub const UNLIMITED: EvalLimits = EvalLimits {
+        max_dice: usize::MAX,
+        max_list_len: usize::MAX,
+        max_draws: usize::MAX,
+        max_depth: usize::MAX,
+        max_steps: usize::MAX,
+    };
+}
+
+impl Default for EvalLimits {
+    fn default() -> Self {
+        Self {
+            max_dice: 10_000,
+            max_list_len: 100_000,
+            max_draws: 1_000_000,
+            max_depth: 256,
+            max_steps: 1_000_000,
+        }
+    }
+}
+
+/// The resources used by an ongoing evaluation, checked against its limits.
+pub(super) struct Usage {
+    limits: EvalLimits,
+    draws: usize,
+    steps: usize,
+    depth: usize,
+}
+
+impl Usage {
+    /// Creates an empty usage with the given limits.
+    pub fn new(limits: EvalLimits) -> Self {
+        Self {
+            limits,
+            draws: 0,
+            steps: 0,
+            depth: 0,
+        }
+    }
+
+    /// Records entering the evaluation of an expression node.
+    pub fn enter(&mut self) -> Result<(), EvalErrorKind> {
+        if self.steps >= self.limits.max_steps {
+            return Err(EvalErrorKind::StepLimitExceeded {
+                limit: self.limits.max_steps,
+            });
+        }
+        if self.depth >= self.limits.max_depth {
+            return Err(EvalErrorKind::DepthLimitExceeded {
+                limit: self.limits.max_depth,
+            });
+        }
+        self.steps += 1;
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Records leaving the evaluation of an expression node.
+    pub fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Records a random draw.
+    pub fn draw(&mut self) -> Result<(), EvalErrorKind> {
+        if self.draws >= self.limits.max_draws {
+            return Err(EvalErrorKind::DrawLimitExceeded {
+                limit: self.limits.max_draws,
+            });
+        }
+        self.draws += 1;
+        Ok(())
+    }
+
+    /// Checks the number of dice requested by a dice roll.
+    pub fn check_dice(&self, requested: i64) -> Result<(), EvalErrorKind> {
+        if usize::try_from(requested).is_ok_and(|count| count > self.limits.max_dice) {
+            return Err(EvalErrorKind::TooManyDice {
+                limit: self.limits.max_dice,
+                requested,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks the length of a list.
+    pub fn check_list_len(&self, len: usize) -> Result<(), EvalErrorKind> {
+        if len > self.limits.max_list_len {
+            return Err(EvalErrorKind::ListTooLong {
+                limit: self.limits.max_list_len,
+                len,
+            });
+        }
+        Ok(())
+    }
+}
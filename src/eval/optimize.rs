@@ -0,0 +1,442 @@
+//! Static simplification of expressions.
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, vec::Vec};
+
+use rand::RngCore;
+
+use super::{EvalContext, EvalError, EvalVisitor, InnerValue, ListInner, dice, limits};
+use crate::ast::{BinaryOperator, Expr, ExprKind, ExprVisitor, Literal, RangeLiteral, Span};
+
+/// A random number generator for evaluating deterministic expressions, which never draw random
+/// numbers.
+struct NoRng;
+
+impl RngCore for NoRng {
+    fn next_u32(&mut self) -> u32 {
+        unreachable!("deterministic expressions draw no random numbers")
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        unreachable!("deterministic expressions draw no random numbers")
+    }
+
+    fn fill_bytes(&mut self, _dst: &mut [u8]) {
+        unreachable!("deterministic expressions draw no random numbers")
+    }
+}
+
+/// Returns `true` if the binary operator draws random numbers, i.e., rolls dice, or shuffles the
+/// elements kept by keep and drop operations.
+fn is_random(op: &BinaryOperator) -> bool {
+    matches!(
+        op,
+        BinaryOperator::DiceRoll
+            | BinaryOperator::KeepHighest
+            | BinaryOperator::KeepLowest
+            | BinaryOperator::DropHighest
+            | BinaryOperator::DropLowest
+    )
+}
+
+/// Returns `true` if the expression is the representation of a value produced by folding.
+fn is_constant(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Literal(_) => true,
+        ExprKind::StrongList(inner) => matches!(inner.kind, ExprKind::Literal(_)),
+        _ => false,
+    }
+}
+
+/// Converts a value into an expression evaluating to it, with the given span.
+fn value_to_expr(value: InnerValue, span: Span) -> Expr {
+    let literal = |literal| Expr::new(ExprKind::Literal(literal), span);
+    match value {
+        InnerValue::Integer(i) => literal(Literal::Int(i)),
+        InnerValue::List { strong, inner } => {
+            let list = literal(match inner {
+                ListInner::List(lst) => Literal::List(lst),
+                ListInner::Range(range) => Literal::Range(range),
+            });
+            if strong {
+                Expr::new(ExprKind::StrongList(Box::new(list)), span)
+            } else {
+                list
+            }
+        }
+    }
+}
+
+/// The optimizer, folding deterministic sub-expressions bottom-up.
+struct Optimizer<'a> {
+    context: &'a EvalContext,
+}
+
+impl Optimizer<'_> {
+    /// Evaluates a deterministic expression whose operands are constants.
+    fn eval(&self, expr: &Expr) -> Result<InnerValue, EvalError> {
+        let mut visitor = EvalVisitor {
+            rng: &mut NoRng,
+            context: self.context,
+            usage: limits::Usage::new(self.context.limits),
+            tracer: None,
+            scope: Vec::new(),
+        };
+        visitor.visit_expr(expr)
+    }
+
+    /// Replaces a constant weak list used as an integer by its sum, as arithmetic sums it anyway.
+    fn sum_operand(&self, expr: Expr) -> Expr {
+        if let ExprKind::Literal(Literal::List(_) | Literal::Range(_)) = &expr.kind
+            && let Ok(value) = self.eval(&expr)
+            && let Ok(Ok(sum)) = value.try_into_integer(self.context.arithmetic)
+        {
+            return Expr::new(ExprKind::Literal(Literal::Int(sum)), expr.span);
+        }
+        expr
+    }
+
+    /// Replaces a list of consecutive ascending faces by the equivalent range, or the number of
+    /// sides if it starts at 1.
+    fn normalize_sides(&self, sides: Expr) -> Expr {
+        let ExprKind::Literal(Literal::List(lst)) = &sides.kind else {
+            return sides;
+        };
+        if lst.is_empty() || !dice::is_consecutive(lst) {
+            return sides;
+        }
+
+        let (start, end) = (lst[0], lst[lst.len() - 1]);
+        let literal = if start == 1 {
+            Literal::Int(end)
+        } else {
+            Literal::Range(RangeLiteral {
+                start,
+                end,
+                step: None,
+            })
+        };
+        Expr::new(ExprKind::Literal(literal), sides.span)
+    }
+
+    /// Optimizes an expression.
+    fn optimize(&self, expr: &Expr) -> Expr {
+        let span = expr.span;
+        let optimized = |kind| Expr::new(kind, span);
+        let boxed = |expr| Box::new(self.optimize(expr));
+
+        let (expr, foldable) = match &expr.kind {
+            ExprKind::Literal(_) => return expr.clone(),
+            ExprKind::UnaryOp { op, expr } => {
+                let expr = self.sum_operand(self.optimize(expr));
+                let foldable = is_constant(&expr);
+                let kind = ExprKind::UnaryOp {
+                    op: *op,
+                    expr: Box::new(expr),
+                };
+                (optimized(kind), foldable)
+            }
+            ExprKind::BinaryOp {
+                left,
+                op: BinaryOperator::DiceRoll,
+                right,
+            } => {
+                let kind = ExprKind::BinaryOp {
+                    left: boxed(left),
+                    op: BinaryOperator::DiceRoll,
+                    right: Box::new(self.normalize_sides(self.optimize(right))),
+                };
+                (optimized(kind), false)
+            }
+            ExprKind::BinaryOp { left, op, right } if is_random(op) => {
+                let kind = ExprKind::BinaryOp {
+                    left: boxed(left),
+                    op: *op,
+                    right: boxed(right),
+                };
+                (optimized(kind), false)
+            }
+            ExprKind::BinaryOp { left, op, right } => {
+                let left = self.sum_operand(self.optimize(left));
+                let right = self.sum_operand(self.optimize(right));
+                let foldable = is_constant(&left) && is_constant(&right);
+                let kind = ExprKind::BinaryOp {
+                    left: Box::new(left),
+                    op: *op,
+                    right: Box::new(right),
+                };
+                (optimized(kind), foldable)
+            }
+            ExprKind::ModifiedDice {
+                count,
+                sides,
+                modifiers,
+            } => {
+                let kind = ExprKind::ModifiedDice {
+                    count: boxed(count),
+                    sides: Box::new(self.normalize_sides(self.optimize(sides))),
+                    modifiers: modifiers.clone(),
+                };
+                (optimized(kind), false)
+            }
+            ExprKind::FunctionCall { name, args } => {
+                let args: Vec<_> = args.iter().map(|arg| self.optimize(arg)).collect();
+                // user-defined functions may draw random numbers or have side effects
+                let foldable =
+                    !self.context.functions.contains(name) && args.iter().all(is_constant);
+                let kind = ExprKind::FunctionCall {
+                    name: name.clone(),
+                    args,
+                };
+                (optimized(kind), foldable)
+            }
+            ExprKind::StrongList(inner) => {
+                let inner = self.optimize(inner);
+                let foldable = is_constant(&inner) && !is_constant(expr);
+                (optimized(ExprKind::StrongList(Box::new(inner))), foldable)
+            }
+            // variables are looked up in the evaluation, even host variables
+            ExprKind::Variable { .. } => return expr.clone(),
+            ExprKind::Let { name, value, body } => {
+                let kind = ExprKind::Let {
+                    name: name.clone(),
+                    value: boxed(value),
+                    body: boxed(body),
+                };
+                (optimized(kind), false)
+            }
+            ExprKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let condition = self.optimize(condition);
+                // conditions failing to evaluate are kept, to fail in the evaluation
+                let taken = if is_constant(&condition) {
+                    let value = self.eval(&condition).ok();
+                    value.and_then(|value| value.into_condition(self.context.arithmetic).ok())
+                } else {
+                    None
+                };
+                match taken {
+                    Some(true) => return self.optimize(then_branch),
+                    Some(false) => return self.optimize(else_branch),
+                    None => {}
+                }
+                let kind = ExprKind::If {
+                    condition: Box::new(condition),
+                    then_branch: boxed(then_branch),
+                    else_branch: boxed(else_branch),
+                };
+                (optimized(kind), false)
+            }
+        };
+
+        if !foldable {
+            return expr;
+        }
+        // expressions failing to evaluate are kept, to fail in the evaluation
+        match self.eval(&expr) {
+            Ok(value) => value_to_expr(value, span),
+            Err(_) => expr,
+        }
+    }
+}
+
+/// Simplifies a RollKit expression for repeated evaluation, with the default
+/// [evaluation context](EvalContext). See [`optimize_with_context`] for details.
+///
+/// # Examples
+///
+/// ```
+/// # use rollkit::{optimize, parse};
+/// let expr = optimize(&parse("2 * 3 + 1d{1, 2, 3, 4, 5, 6} + [1, 4]").unwrap());
+/// assert_eq!(expr, parse("6 + 1d6 + 10").unwrap());
+/// ```
+pub fn optimize(expr: &Expr) -> Expr {
+    optimize_with_context(expr, &EvalContext::default())
+}
+
+/// Simplifies a RollKit expression for repeated evaluation with the given
+/// [evaluation context](EvalContext).
+///
+/// The optimized expression evaluates to the same results as the original one with the same
+/// context and random number generator, except that it takes fewer steps against
+/// [`EvalLimits::max_steps`](super::EvalLimits::max_steps). The optimizer:
+///
+/// - Folds sub-expressions that draw no random numbers into literals, e.g., `2 * 3` into `6`, and
+///   `sum({1, 2, 3})` into `6`. Sub-expressions failing to evaluate, e.g., `1 / 0`, are kept.
+/// - Replaces constant weak lists used as integers by their sums, e.g., `1d20 + [1, 4]` into
+///   `1d20 + 10`.
+/// - Normalizes dice faces listed one by one to ranges, e.g., `1d{1, 2, 3, 4, 5, 6}` into `1d6`.
+/// - Replaces conditionals with constant conditions by the branch taken, e.g.,
+///   `if 2 > 1 then 1d6 else 1d8` into `1d6`.
+///
+/// Variables are not substituted, so sub-expressions referring to them are kept.
+///
+/// Keep and drop operations are never folded, as they shuffle the kept elements with the random
+/// number generator, and neither are calls of user-defined functions.
+///
+/// # Examples
+///
+/// ```
+/// # use rand::{SeedableRng, rngs::StdRng};
+/// # use rollkit::{EvalContext, eval_with_context, optimize_with_context, parse};
+/// let context = EvalContext::default();
+/// let expr = parse("{3d6} * (2 ** 3 - 1) + 1d{0, 1, 2}").unwrap();
+/// let optimized = optimize_with_context(&expr, &context);
+/// assert_eq!(optimized, parse("{3d6} * 7 + 1d[0, 2]").unwrap());
+///
+/// let mut rng = StdRng::seed_from_u64(0);
+/// let expected = eval_with_context(&expr, &mut rng, &context);
+/// let mut rng = StdRng::seed_from_u64(0);
+/// assert_eq!(eval_with_context(&optimized, &mut rng, &context), expected);
+/// ```
+pub fn optimize_with_context(expr: &Expr, context: &EvalContext) -> Expr {
+    Optimizer { context }.optimize(expr)
+}
+
+/// Returns `true` if evaluating the RollKit expression with the default
+/// [evaluation context](EvalContext) draws no random numbers, i.e., it always results in the same
+/// value, or always fails the same way. See [`is_deterministic_with_context`] for details.
+///
+/// # Examples
+///
+/// ```
+/// # use rollkit::{is_deterministic, parse};
+/// assert!(is_deterministic(&parse("max({1, 2, 3}) * 2").unwrap()));
+/// assert!(!is_deterministic(&parse("1 + 1d6").unwrap()));
+/// ```
+pub fn is_deterministic(expr: &Expr) -> bool {
+    is_deterministic_with_context(expr, &EvalContext::default())
+}
+
+/// Returns `true` if evaluating the RollKit expression with the given
+/// [evaluation context](EvalContext) draws no random numbers.
+///
+/// Expressions are deterministic unless they contain dice rolls, keep and drop operations, which
+/// shuffle the kept elements, or calls of user-defined functions, which may draw random numbers.
+/// Such expressions are not deterministic even if their results are, e.g., `0d6`.
+pub fn is_deterministic_with_context(expr: &Expr, context: &EvalContext) -> bool {
+    match &expr.kind {
+        ExprKind::Literal(_) => true,
+        ExprKind::UnaryOp { expr, .. } | ExprKind::StrongList(expr) => {
+            is_deterministic_with_context(expr, context)
+        }
+        ExprKind::BinaryOp { left, op, right } => {
+            !is_random(op)
+                && is_deterministic_with_context(left, context)
+                && is_deterministic_with_context(right, context)
+        }
+        ExprKind::ModifiedDice { .. } => false,
+        ExprKind::Variable { .. } => true,
+        ExprKind::Let { value, body, .. } => {
+            is_deterministic_with_context(value, context)
+                && is_deterministic_with_context(body, context)
+        }
+        ExprKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            is_deterministic_with_context(condition, context)
+                && is_deterministic_with_context(then_branch, context)
+                && is_deterministic_with_context(else_branch, context)
+        }
+        ExprKind::FunctionCall { name, args } => {
+            !context.functions.contains(name)
+                && args
+                    .iter()
+                    .all(|arg| is_deterministic_with_context(arg, context))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::{FunctionRegistry, Value, eval_with_context, parse};
+
+    #[test]
+    fn test_optimize() {
+        let cases = [
+            ("1 + 2 * 3", "7"),
+            ("[1, 4] + 1d20", "10 + 1d20"),
+            ("-[1, 3] * 1d4", "-6 * 1d4"),
+            ("{[1, 3]} * 2", "{{2, 4, 6}}"),
+            ("{1, 2} * 1d4", "3 * 1d4"),
+            ("{{1, 2}} * 1d4", "{{1, 2}} * 1d4"),
+            ("sum(sort({3, 1, 2})) + 1d6", "6 + 1d6"),
+            ("1d{1, 2, 3}", "1d3"),
+            ("(1 + 1)d{2, 3, 4}!", "2d[2, 4]!"),
+            ("1d{1, 3, 2}", "1d{1, 3, 2}"),
+            ("{4, 2, 5}kh2", "{4, 2, 5}kh2"),
+            ("1 / 0 + 2 * 3", "1 / 0 + 6"),
+            ("max(1d6, 2 + 3)", "max(1d6, 5)"),
+            ("double(1 + 1)", "double(2)"),
+            ("let x = 1 + 2; x * (2 + 2)", "let x = 3; x * 4"),
+            ("let x = 1d6; {x} * (1 + 1)", "let x = 1d6; {x} * 2"),
+            ("if 2 > 1 then 1d6 else 1 / 0", "1d6"),
+            ("1 + (if {1, -1} then 2 else 3 * 3)", "10"),
+            ("if {{1}} then 1 else 2", "if {{1}} then 1 else 2"),
+            ("if 1d2 then 1 + 1 else 2", "if 1d2 then 2 else 2"),
+        ];
+
+        let mut functions = FunctionRegistry::new();
+        functions.register("double", |args| Ok(Value::Integer(args[0].sum() * 2)));
+        let context = EvalContext {
+            functions,
+            ..Default::default()
+        };
+        for (input, expected) in cases {
+            let expr = parse(input).unwrap();
+            let optimized = optimize_with_context(&expr, &context);
+            assert_eq!(
+                optimized.format_inline(),
+                parse(expected).unwrap().format_inline(),
+                "Input: {}",
+                input
+            );
+            for seed in 0..10 {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let expected = eval_with_context(&expr, &mut rng, &context);
+                let mut rng = StdRng::seed_from_u64(seed);
+                let result = eval_with_context(&optimized, &mut rng, &context);
+                assert_eq!(result, expected, "Input: {}, seed: {}", input, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_is_deterministic() {
+        let cases = [
+            ("1 + 2", true),
+            ("sum([1, 6]) * -2", true),
+            ("1 / 0", true),
+            ("unknown(1)", true),
+            ("1d6", false),
+            ("0d6", false),
+            ("{1, 2}kh1", false),
+            ("max(3, 1d4)", false),
+            ("2d6!", false),
+        ];
+        for (input, expected) in cases {
+            let expr = parse(input).unwrap();
+            assert_eq!(is_deterministic(&expr), expected, "Input: {}", input);
+        }
+
+        let mut functions = FunctionRegistry::new();
+        functions.register("max", |_| Ok(Value::Integer(0)));
+        let context = EvalContext {
+            functions,
+            ..Default::default()
+        };
+        let expr = parse("max(1, 2)").unwrap();
+        assert!(!is_deterministic_with_context(&expr, &context));
+        assert_eq!(optimize_with_context(&expr, &context), expr);
+    }
+}
@@ -0,0 +1,443 @@
+//! Compiling expressions into plans for repeated evaluation.
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+use alloc::{string::String, vec::Vec};
+
+use core::fmt;
+
+use rand::Rng;
+
+use super::{
+    EvalContext, EvalError, EvalErrorKind, EvalVisitor, InnerValue, Value,
+    builtins::{self, Builtin},
+    dice::{self, PreparedDie},
+    limits,
+};
+use crate::ast::{BinaryOperator, DiceModifier, Expr, ExprKind, Literal, Span, UnaryOperator};
+
+/// An instruction of a plan. Instructions operate on a stack of values, and each expression node
+/// is compiled into an [`Enter`](Instr::Enter), the instructions of its operands, and an
+/// instruction computing its value from the operands on the stack. Conditionals jump over the
+/// instructions of the branch not taken.
+enum Instr {
+    /// Enters an expression node, checking the limits.
+    Enter(Span),
+    /// Leaves an expression node which pushes no value, i.e., the sides of a prepared dice roll.
+    Leave,
+    /// Fails the evaluation of the node entered last.
+    Fail(EvalErrorKind, Span),
+    /// Pushes a constant, i.e., the value of a literal, or the sum of a list literal used as an
+    /// integer.
+    Constant(InnerValue, Span),
+    /// Applies a unary operator to the top value.
+    UnaryOp(UnaryOperator, Span),
+    /// Applies a binary operator to the top two values.
+    BinaryOp(BinaryOperator, Span),
+    /// Rolls dice with the count and sides on top of the stack and the given modifiers.
+    RollDice(usize, Span),
+    /// Rolls a prepared die, with the count on top of the stack.
+    RollPrepared(usize, Span),
+    /// Calls a built-in function with the given number of arguments on top of the stack.
+    CallBuiltin(&'static Builtin, usize, Span),
+    /// Calls a user-defined function, evaluating its arguments from the expression tree.
+    CallUser(usize, Span),
+    /// Converts the top value into a strong list.
+    StrongList(Span),
+    /// Pushes the value of a variable, and whether it's a host variable.
+    Variable(String, bool, Span),
+    /// Binds the top value to a variable, for the body of a let-binding.
+    Bind(String),
+    /// Unbinds the variable bound last, leaving the let-binding with the value of its body.
+    Unbind(Span),
+    /// Pops the condition of a conditional, and jumps to the given instruction if it's false.
+    Branch(usize, Span),
+    /// Jumps to the given instruction.
+    Jump(usize),
+    /// Leaves a conditional with the value of the branch taken.
+    EndIf(Span),
+}
+
+/// A RollKit expression compiled for repeated evaluation with an
+/// [evaluation context](EvalContext).
+///
+/// Evaluating a plan gives the same results as [`eval_with_context`](super::eval_with_context) with
+/// the same random number generator, including errors and their spans, but avoids most of the
+/// work repeated in each evaluation: the expression tree is flattened into a list of
+/// instructions, function names are resolved, list literals used as integers, e.g., in
+/// `[1, 10] + 1d6`, are summed once, and dice with constant sides, e.g., `3d6` or
+/// `1d{1, 1, 2}`, have their faces expanded and modifiers checked once.
+///
+/// # Example
+///
+/// ```
+/// # use rand::{SeedableRng, rngs::StdRng};
+/// # use rollkit::{EvalContext, EvalPlan, eval_with, parse};
+/// let expr = parse("4d6kh3 + 1d{-1, 0, 1}").unwrap();
+/// let context = EvalContext::default();
+/// let plan = EvalPlan::compile(&expr, &context);
+///
+/// let mut rng = StdRng::seed_from_u64(42);
+/// let total: i64 = (0..1000).map(|_| plan.eval(&mut rng).unwrap().sum()).sum();
+/// println!("average: {}", total as f64 / 1000.0);
+///
+/// // the same results as evaluating the expression
+/// let result = plan.eval(&mut StdRng::seed_from_u64(0));
+/// assert_eq!(result, eval_with(&expr, &mut StdRng::seed_from_u64(0)));
+/// ```
+pub struct EvalPlan<'a> {
+    context: &'a EvalContext,
+    instrs: Vec<Instr>,
+    /// The modifiers of the dice rolls with non-constant sides.
+    modifiers: Vec<Vec<DiceModifier>>,
+    /// The dice of the dice rolls with constant sides, or the errors preparing them.
+    dice: Vec<Result<PreparedDie, EvalErrorKind>>,
+    /// The names and arguments of calls of user-defined functions.
+    calls: Vec<(String, Vec<Expr>)>,
+}
+
+impl<'a> EvalPlan<'a> {
+    /// Compiles an expression to be evaluated with the given context.
+    ///
+    /// Compiling never fails. Expressions that would fail to evaluate, e.g., `1d0` or
+    /// `unknown(1)`, fail each evaluation of the plan with the same error.
+    pub fn compile(expr: &Expr, context: &'a EvalContext) -> Self {
+        let mut plan = EvalPlan {
+            context,
+            instrs: Vec::new(),
+            modifiers: Vec::new(),
+            dice: Vec::new(),
+            calls: Vec::new(),
+        };
+        plan.compile_expr(expr);
+        plan
+    }
+
+    /// Compiles an expression node.
+    fn compile_expr(&mut self, expr: &Expr) {
+        let span = expr.span;
+        self.instrs.push(Instr::Enter(span));
+        match &expr.kind {
+            ExprKind::Literal(literal) => {
+                let value = InnerValue::from_literal(literal);
+                self.instrs.push(Instr::Constant(value, span));
+            }
+            ExprKind::UnaryOp { op, expr } => {
+                self.compile_operand(expr);
+                self.instrs.push(Instr::UnaryOp(*op, span));
+            }
+            ExprKind::BinaryOp {
+                left,
+                op: BinaryOperator::DiceRoll,
+                right,
+            } => self.compile_dice(left, right, &[], span),
+            ExprKind::BinaryOp {
+                left,
+                op:
+                    op @ (BinaryOperator::KeepHighest
+                    | BinaryOperator::KeepLowest
+                    | BinaryOperator::DropHighest
+                    | BinaryOperator::DropLowest),
+                right,
+            } => {
+                self.compile_expr(left);
+                self.compile_expr(right);
+                self.instrs.push(Instr::BinaryOp(*op, span));
+            }
+            ExprKind::BinaryOp { left, op, right } => {
+                self.compile_operand(left);
+                self.compile_operand(right);
+                self.instrs.push(Instr::BinaryOp(*op, span));
+            }
+            ExprKind::ModifiedDice {
+                count,
+                sides,
+                modifiers,
+            } => self.compile_dice(count, sides, modifiers, span),
+            ExprKind::FunctionCall { name, args } => {
+                if self.context.functions.contains(name) {
+                    self.calls.push((name.clone(), args.clone()));
+                    self.instrs
+                        .push(Instr::CallUser(self.calls.len() - 1, span));
+                } else if let Some(builtin) = builtins::lookup(name) {
+                    for arg in args {
+                        self.compile_expr(arg);
+                    }
+                    self.instrs
+                        .push(Instr::CallBuiltin(builtin, args.len(), span));
+                } else {
+                    let kind = EvalErrorKind::UnknownFunction { name: name.clone() };
+                    self.instrs.push(Instr::Fail(kind, span));
+                }
+            }
+            ExprKind::StrongList(expr) => {
+                self.compile_expr(expr);
+                self.instrs.push(Instr::StrongList(span));
+            }
+            ExprKind::Variable { name, host } => {
+                self.instrs.push(Instr::Variable(name.clone(), *host, span));
+            }
+            ExprKind::Let { name, value, body } => {
+                self.compile_expr(value);
+                self.instrs.push(Instr::Bind(name.clone()));
+                self.compile_expr(body);
+                self.instrs.push(Instr::Unbind(span));
+            }
+            ExprKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.compile_expr(condition);
+                let branch = self.instrs.len();
+                self.instrs.push(Instr::Branch(0, span));
+                self.compile_expr(then_branch);
+                let jump = self.instrs.len();
+                self.instrs.push(Instr::Jump(0));
+                self.instrs[branch] = Instr::Branch(self.instrs.len(), span);
+                self.compile_expr(else_branch);
+                self.instrs[jump] = Instr::Jump(self.instrs.len());
+                self.instrs.push(Instr::EndIf(span));
+            }
+        }
+    }
+
+    /// Compiles an operand of arithmetic or comparisons, which sum up weak lists. The sums of list
+    /// literals are computed once.
+    fn compile_operand(&mut self, expr: &Expr) {
+        if let ExprKind::Literal(literal @ (Literal::List(_) | Literal::Range(_))) = &expr.kind {
+            let value = InnerValue::from_literal(literal);
+            // literals too long for the limits, or overflowing, are left to fail in the evaluation
+            if let InnerValue::List { inner, .. } = &value
+                && inner.len() <= self.context.limits.max_list_len
+                && let Ok(Ok(sum)) = value.try_into_integer(self.context.arithmetic)
+            {
+                self.instrs.push(Instr::Enter(expr.span));
+                let sum = InnerValue::Integer(sum);
+                self.instrs.push(Instr::Constant(sum, expr.span));
+                return;
+            }
+        }
+        self.compile_expr(expr);
+    }
+
+    /// Compiles a dice roll. If the sides are a literal, the die is prepared.
+    fn compile_dice(&mut self, count: &Expr, sides: &Expr, modifiers: &[DiceModifier], span: Span) {
+        self.compile_expr(count);
+
+        // literals too long for the limits are left to fail in the evaluation
+        let sides_value = match &sides.kind {
+            ExprKind::Literal(literal) => Some(InnerValue::from_literal(literal)).filter(|value| {
+                !matches!(value, InnerValue::List { inner, .. }
+                    if inner.len() > self.context.limits.max_list_len)
+            }),
+            _ => None,
+        };
+
+        match sides_value {
+            Some(value) => {
+                // the sides are still entered, as in the evaluation
+                self.instrs.push(Instr::Enter(sides.span));
+                self.instrs.push(Instr::Leave);
+                self.dice.push(PreparedDie::new(value, modifiers));
+                self.instrs
+                    .push(Instr::RollPrepared(self.dice.len() - 1, span));
+            }
+            None => {
+                self.compile_expr(sides);
+                self.modifiers.push(modifiers.to_vec());
+                self.instrs
+                    .push(Instr::RollDice(self.modifiers.len() - 1, span));
+            }
+        }
+    }
+
+    /// Evaluates the plan with a random number generator.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rand::{SeedableRng, rngs::StdRng};
+    /// # use rollkit::{EvalContext, EvalPlan, Value, parse};
+    /// let context = EvalContext::default();
+    /// let plan = EvalPlan::compile(&parse("{2d1} * 3").unwrap(), &context);
+    /// assert_eq!(plan.eval(&mut StdRng::seed_from_u64(0)), Ok(Value::List(vec![3, 3])));
+    /// ```
+    pub fn eval<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<Value, EvalError> {
+        let mut visitor = EvalVisitor {
+            rng,
+            context: self.context,
+            usage: limits::Usage::new(self.context.limits),
+            tracer: None,
+            scope: Vec::new(),
+        };
+        let mut stack: Vec<InnerValue> = Vec::new();
+
+        let mut next = 0;
+        while let Some(instr) = self.instrs.get(next) {
+            next += 1;
+            let (span, result) = match instr {
+                Instr::Enter(span) => {
+                    visitor.enter(*span)?;
+                    continue;
+                }
+                Instr::Leave => {
+                    visitor.usage.leave();
+                    continue;
+                }
+                Instr::Fail(kind, span) => (span, Err(kind.clone().into())),
+                Instr::Constant(value, span) => (span, Ok(value.clone())),
+                Instr::UnaryOp(op, span) => {
+                    let value = stack.pop().unwrap();
+                    (span, visitor.unary_op(op, value).map_err(EvalError::from))
+                }
+                Instr::BinaryOp(op, span) => {
+                    let right = stack.pop().unwrap();
+                    let left = stack.pop().unwrap();
+                    (
+                        span,
+                        visitor.binary_op(op, left, right).map_err(EvalError::from),
+                    )
+                }
+                Instr::RollDice(modifiers, span) => {
+                    let sides = stack.pop().unwrap();
+                    let count = stack.pop().unwrap();
+                    let modifiers = &self.modifiers[*modifiers];
+                    let result = dice::roll_dice(&mut visitor, count, sides, modifiers);
+                    (span, result.map_err(EvalError::from))
+                }
+                Instr::RollPrepared(die, span) => {
+                    let count = stack.pop().unwrap();
+                    let result = dice::check_count(&visitor, count).and_then(|count| {
+                        let die = self.dice[*die].as_ref().map_err(Clone::clone)?;
+                        dice::roll_prepared(&mut visitor, count, die)
+                    });
+                    (span, result.map_err(EvalError::from))
+                }
+                Instr::CallBuiltin(builtin, args, span) => {
+                    let args = stack.split_off(stack.len() - args);
+                    let result = (builtin.func)(builtin, args, self.context.arithmetic);
+                    (span, result.map_err(EvalError::from))
+                }
+                Instr::CallUser(call, span) => {
+                    let (name, args) = &self.calls[*call];
+                    let result = self.context.functions.call(name, args, &mut visitor);
+                    let result = result.expect("compiled calls are of registered functions");
+                    (span, result.map(InnerValue::from_public))
+                }
+                Instr::StrongList(span) => (span, Ok(stack.pop().unwrap().into_strong())),
+                Instr::Variable(name, host, span) => {
+                    (span, visitor.variable(name, *host).map_err(EvalError::from))
+                }
+                Instr::Bind(name) => {
+                    let value = stack.pop().unwrap();
+                    visitor.scope.push((name.clone(), value));
+                    continue;
+                }
+                Instr::Unbind(span) => {
+                    visitor.scope.pop();
+                    (span, Ok(stack.pop().unwrap()))
+                }
+                Instr::Branch(target, span) => {
+                    match stack.pop().unwrap().into_condition(self.context.arithmetic) {
+                        Ok(true) => continue,
+                        Ok(false) => {
+                            next = *target;
+                            continue;
+                        }
+                        Err(kind) => (span, Err(kind.into())),
+                    }
+                }
+                Instr::Jump(target) => {
+                    next = *target;
+                    continue;
+                }
+                Instr::EndIf(span) => (span, Ok(stack.pop().unwrap())),
+            };
+            stack.push(visitor.leave(*span, result)?);
+        }
+
+        Ok(stack.pop().unwrap().into_public())
+    }
+}
+
+impl fmt::Debug for EvalPlan<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EvalPlan")
+            .field("instructions", &self.instrs.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::{EvalLimits, FunctionRegistry, eval_with_context, parse};
+
+    #[test]
+    fn test_plan_matches_eval() {
+        let mut functions = FunctionRegistry::new();
+        functions.register("double", |args| Ok(Value::Integer(args[0].sum() * 2)));
+        functions.register("sum", |_| Ok(Value::Integer(7)));
+        let contexts = [
+            EvalContext {
+                functions,
+                ..Default::default()
+            },
+            EvalContext {
+                limits: EvalLimits {
+                    max_steps: 6,
+                    max_depth: 3,
+                    max_list_len: 4,
+                    max_draws: 10,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ];
+        let inputs = [
+            "4d6kh3 + 2",
+            "3d6!r1 - 1d{1, 1, 2}",
+            "{5d[1, 11, 2]}dl2 * 2",
+            "{3d6} >= 4",
+            "(1d4)d6",
+            "(1d4 + 0)d(1d6 + 0)",
+            "1d0 + 1",
+            "2d6r<7",
+            "1d[1, 10]",
+            "max(2d20) + double(1d4) + sum(3d6)",
+            "unknown(1d6)",
+            "1 / (1d2 - 1)",
+            "8d6>=5f1",
+            "-(1d6)",
+            "let x = 1d6; {x} + x",
+            "let x = 3d6; let y = x kh 1; double(y) + x",
+            "let x = 1d4; x + unbound",
+            "if 1d2 == 2 then 3d6 else 1d4 + 1",
+            "if {1d2} then 1 else 2",
+            "let x = 1d20; if x >= 10 then (if x == 20 then 2d8 else 1d8) else 0",
+            "max(if 1d2 > 1 then 4d6 else 2d6) + 1",
+            "{1, 2, 3} + 1d4 - [1, 10, 3]",
+            "-[1, 10] * {{1d6}}",
+            "{1, 2, 3, 4, 5} >= 1d6",
+            "{9223372036854775807, 1} + 1d6",
+            "{1, 2, 3}kh1 + {{1, 2}}",
+        ];
+
+        for context in &contexts {
+            for input in inputs {
+                let expr = parse(input).unwrap();
+                let plan = EvalPlan::compile(&expr, context);
+                for seed in 0..20 {
+                    let expected =
+                        eval_with_context(&expr, &mut StdRng::seed_from_u64(seed), context);
+                    let result = plan.eval(&mut StdRng::seed_from_u64(seed));
+                    assert_eq!(result, expected, "Input: {}, seed: {}", input, seed);
+                }
+            }
+        }
+    }
+}
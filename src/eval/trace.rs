@@ -0,0 +1,285 @@
+//! Tracing evaluation, recording how every expression node was evaluated.
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use rand::Rng;
+#[cfg(feature = "std")]
+use rand::rng;
+
+use super::{EvalContext, EvalError, EvalVisitor, Value, limits};
+use crate::ast::{Expr, ExprVisitor, Span};
+
+/// The record of evaluating an expression node, produced by [`eval_traced_with_context`] and
+/// friends.
+///
+/// A node is recorded each time it's evaluated, so a node can have multiple records, e.g., when a
+/// user-defined function evaluates an argument multiple times, or none at all.
+///
+/// # Example
+///
+/// ```
+/// # use rand::{SeedableRng, rngs::StdRng};
+/// # use rollkit::{Value, eval_traced_with, parse};
+/// let input = "4d6kh3 + 2";
+/// let trace = eval_traced_with(&parse(input).unwrap(), &mut StdRng::from_os_rng()).unwrap();
+///
+/// // the root node holds the result
+/// let Value::Integer(result) = trace.value else { unreachable!() };
+///
+/// let keep = &trace.children[0];
+/// let dice = &keep.children[0];
+/// assert_eq!(&input[keep.span.into_range()], "4d6kh3");
+/// assert_eq!(&input[dice.span.into_range()], "4d6");
+/// assert_eq!(dice.rolls.len(), 4);
+/// assert_eq!(keep.dropped.len(), 1);
+///
+/// let total: i64 = dice.rolls.iter().map(|roll| roll.face).sum();
+/// assert_eq!(result, total - keep.dropped[0] + 2);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceNode {
+    /// The span of the expression node.
+    pub span: Span,
+    /// The value the node evaluated to.
+    pub value: Value,
+    /// The dice rolled by the node itself, in the order they were drawn, including rerolled and
+    /// exploded ones. Only dice rolls have dice.
+    pub rolls: Vec<DieRoll>,
+    /// The elements removed from the list by the node itself. Only keep and drop operations drop
+    /// elements.
+    pub dropped: Vec<i64>,
+    /// The records of the sub-expressions evaluated for the node, in evaluation order.
+    pub children: Vec<TraceNode>,
+}
+
+impl TraceNode {
+    /// Creates a record of a node yet to be evaluated.
+    fn new(span: Span) -> Self {
+        Self {
+            span,
+            value: Value::Integer(0),
+            rolls: Vec::new(),
+            dropped: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+}
+
+/// A single roll of a die, as recorded in a [`TraceNode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DieRoll {
+    /// The face rolled.
+    pub face: i64,
+    /// The value of the roll, i.e., the face, or the face minus 1 for the extra rolls of
+    /// penetrating explosions.
+    pub value: i64,
+    /// The roll was discarded and the die rolled again by a reroll modifier.
+    pub rerolled: bool,
+    /// The roll triggered an explosion, i.e., an extra roll of the die.
+    pub exploded: bool,
+    /// The roll is an extra roll from an explosion. For compounding explosions, the face is added
+    /// to the die that exploded, and for penetrating explosions, the face minus 1 is added as a new
+    /// die.
+    pub extra: bool,
+}
+
+/// Collects the records of an ongoing evaluation.
+pub(super) struct Tracer {
+    /// The records of the nodes being evaluated, innermost last.
+    stack: Vec<TraceNode>,
+    /// The record of the root node, once it's evaluated.
+    root: Option<TraceNode>,
+}
+
+impl Tracer {
+    pub fn new() -> Self {
+        Self {
+            stack: Vec::new(),
+            root: None,
+        }
+    }
+
+    /// Records entering the evaluation of an expression node.
+    pub fn enter(&mut self, span: Span) {
+        self.stack.push(TraceNode::new(span));
+    }
+
+    /// Records leaving the evaluation of an expression node, with its value, or `None` if it
+    /// failed, in which case the node isn't recorded.
+    pub fn leave(&mut self, value: Option<Value>) {
+        let Some(mut node) = self.stack.pop() else {
+            return;
+        };
+        let Some(value) = value else {
+            return;
+        };
+
+        node.value = value;
+        match self.stack.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => self.root = Some(node),
+        }
+    }
+
+    /// Records a roll of a die by the innermost node.
+    pub fn roll(&mut self, roll: DieRoll) {
+        if let Some(node) = self.stack.last_mut() {
+            node.rolls.push(roll);
+        }
+    }
+
+    /// Records elements dropped from a list by the innermost node.
+    pub fn drop(&mut self, dropped: &[i64]) {
+        if let Some(node) = self.stack.last_mut() {
+            node.dropped.extend_from_slice(dropped);
+        }
+    }
+}
+
+/// Evaluates a RollKit expression like [`eval`](super::eval), and returns the record of the
+/// evaluation, whose root holds the result.
+///
+/// The expression is evaluated using the default thread-local random number generator
+/// [`rand::rng`], and therefore requires the standard library. See [`eval_traced_with_context`]
+/// for details.
+///
+/// # Examples
+///
+/// ```
+/// # use rollkit::{eval_traced, parse};
+/// let trace = eval_traced(&parse("3d6 + 2").unwrap()).unwrap();
+/// println!("Result: {}, dice: {:?}", trace.value, trace.children[0].rolls);
+/// ```
+#[cfg(feature = "std")]
+pub fn eval_traced(expr: &Expr) -> Result<TraceNode, EvalError> {
+    eval_traced_with(expr, &mut rng())
+}
+
+/// Evaluates a RollKit expression with a provided random number generator like
+/// [`eval_with`](super::eval_with), and returns the record of the evaluation, whose root holds the
+/// result. See [`eval_traced_with_context`] for details.
+pub fn eval_traced_with<R: Rng + ?Sized>(expr: &Expr, rng: &mut R) -> Result<TraceNode, EvalError> {
+    eval_traced_with_context(expr, rng, &EvalContext::default())
+}
+
+/// Evaluates a RollKit expression with a provided random number generator and
+/// [evaluation context](EvalContext) like [`eval_with_context`](super::eval_with_context), and
+/// returns the record of the evaluation, whose root holds the result.
+///
+/// Tracing doesn't change the evaluation, i.e., with the same random number generator, the result
+/// is the same as without tracing.
+///
+/// # Examples
+///
+/// ```
+/// # use rand::{SeedableRng, rngs::StdRng};
+/// # use rollkit::{EvalContext, eval_traced_with_context, parse};
+/// let context = EvalContext::default();
+/// let expr = parse("2d6r1!").unwrap();
+/// let trace = eval_traced_with_context(&expr, &mut StdRng::from_os_rng(), &context).unwrap();
+///
+/// let dice = &trace.rolls;
+/// assert!(dice.iter().all(|roll| roll.rerolled == (roll.face == 1)));
+/// assert!(dice.iter().all(|roll| roll.exploded == (roll.face == 6)));
+/// let extra = dice.iter().filter(|roll| roll.extra && !roll.rerolled).count();
+/// assert_eq!(extra, dice.iter().filter(|roll| roll.exploded).count());
+/// ```
+pub fn eval_traced_with_context<R: Rng + ?Sized>(
+    expr: &Expr,
+    rng: &mut R,
+    context: &EvalContext,
+) -> Result<TraceNode, EvalError> {
+    let mut visitor = EvalVisitor {
+        rng,
+        context,
+        usage: limits::Usage::new(context.limits),
+        tracer: Some(Tracer::new()),
+        scope: Vec::new(),
+    };
+    visitor.visit_expr(expr)?;
+    Ok(visitor
+        .tracer
+        .and_then(|tracer| tracer.root)
+        .expect("the root node is recorded after a successful evaluation"))
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::{eval_with, parse};
+
+    #[test]
+    fn test_trace_matches_eval() {
+        let inputs = [
+            "4d6kh3 + 2",
+            "{3d6!}dl1 + 2d10r<3",
+            "max(4d6!!, [1, 6]) * 2",
+            "10d10>=8f1",
+            "-(2d6ro1)^2",
+        ];
+
+        for input in inputs {
+            let expr = parse(input).unwrap();
+            for seed in 0..20 {
+                let expected = eval_with(&expr, &mut StdRng::seed_from_u64(seed)).unwrap();
+                let trace = eval_traced_with(&expr, &mut StdRng::seed_from_u64(seed)).unwrap();
+                assert_eq!(trace.value, expected, "Input: {}", input);
+                assert_eq!(trace.span, expr.span, "Input: {}", input);
+            }
+        }
+    }
+
+    #[test]
+    fn test_trace_rolls() {
+        let context = EvalContext {
+            explode_limit: 3,
+            ..Default::default()
+        };
+        let trace = |input: &str| {
+            let expr = parse(input).unwrap();
+            eval_traced_with_context(&expr, &mut StdRng::seed_from_u64(0), &context).unwrap()
+        };
+        let roll = |face, rerolled, exploded, extra| DieRoll {
+            face,
+            value: face,
+            rerolled,
+            exploded,
+            extra,
+        };
+
+        // compounding explosions record each roll, and the die holds the total
+        let node = trace("1d1!!");
+        assert_eq!(node.value, Value::List(vec![4]));
+        assert_eq!(
+            node.rolls,
+            [
+                roll(1, false, true, false),
+                roll(1, false, true, true),
+                roll(1, false, true, true),
+                roll(1, false, false, true),
+            ]
+        );
+
+        // penetrating explosions record the faces rolled and the values added
+        let node = trace("1d1!p");
+        assert_eq!(node.value, Value::List(vec![1, 0, 0, 0]));
+        let values: Vec<_> = node.rolls.iter().map(|r| (r.face, r.value)).collect();
+        assert_eq!(values, [(1, 1), (1, 0), (1, 0), (1, 0)]);
+
+        let node = trace("3d{1, 2}r1");
+        assert_eq!(node.value, Value::List(vec![2, 2, 2]));
+        assert!(node.rolls.iter().all(|r| r.rerolled == (r.face == 1)));
+        assert_eq!(node.rolls.iter().filter(|r| !r.rerolled).count(), 3);
+
+        // keep and drop record the dropped elements, and literals record nothing
+        let node = trace("[1, 5]dh2");
+        assert_eq!(node.dropped, [4, 5]);
+        assert_eq!(node.children.len(), 2);
+        assert!(node.children.iter().all(|child| child.rolls.is_empty()));
+    }
+}
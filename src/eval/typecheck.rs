@@ -0,0 +1,618 @@
+//! Static type checking of expressions.
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use core::fmt;
+
+use super::{ArithmeticMode, EvalContext, EvalErrorKind, InnerValue, ListInner, Value, builtins};
+use crate::ast::{BinaryOperator, DiceModifier, Expr, ExprKind, Literal, Span};
+
+/// The type of a value during evaluation, mirroring the internal representation of values.
+///
+/// Weak lists, e.g., list literals and dice rolls, are summed up when used as integers, while
+/// strong lists, e.g., `{3d6}`, apply arithmetic and comparisons element-wise. Both are returned
+/// as [`Value::List`](super::Value::List) from evaluation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    /// An integer.
+    Integer,
+    /// A weak list.
+    WeakList,
+    /// A strong list.
+    StrongList,
+}
+
+impl ValueType {
+    /// Returns the type of a value.
+    fn of(value: &InnerValue) -> Self {
+        match value {
+            InnerValue::Integer(_) => ValueType::Integer,
+            InnerValue::List { strong: false, .. } => ValueType::WeakList,
+            InnerValue::List { strong: true, .. } => ValueType::StrongList,
+        }
+    }
+
+    /// Returns a value of the type, to evaluate built-in functions on.
+    fn example(self) -> InnerValue {
+        match self {
+            ValueType::Integer => InnerValue::Integer(1),
+            ValueType::WeakList | ValueType::StrongList => InnerValue::List {
+                strong: self == ValueType::StrongList,
+                inner: ListInner::List(vec![1]),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueType::Integer => write!(f, "integer"),
+            ValueType::WeakList => write!(f, "weak list"),
+            ValueType::StrongList => write!(f, "strong list"),
+        }
+    }
+}
+
+/// The kinds of [type errors](TypeError).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeErrorKind {
+    /// An operator expected an integer operand, but got a list.
+    IntegerExpected {
+        /// The operator, e.g., `"kh"`.
+        operator: &'static str,
+        /// The type of the operand.
+        found: ValueType,
+    },
+    /// An operator expected a list operand, but got an integer.
+    ListExpected {
+        /// The operator, e.g., `"kh"`.
+        operator: &'static str,
+    },
+    /// Referred to a variable that is not bound.
+    UnboundVariable {
+        /// Name of the variable as written in the expression, i.e., with the `@` of host
+        /// variables.
+        name: String,
+    },
+    /// Called a function that does not exist.
+    UnknownFunction {
+        /// Name of the function.
+        name: String,
+    },
+    /// Called a built-in function with the wrong number or types of arguments.
+    WrongArguments {
+        /// Name of the function.
+        name: String,
+        /// Description of the arguments the function accepts.
+        expected: &'static str,
+    },
+}
+
+impl fmt::Display for TypeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeErrorKind::IntegerExpected { operator, found } => write!(
+                f,
+                "Operator `{}` expected an integer, but got a {}",
+                operator, found
+            ),
+            TypeErrorKind::ListExpected { operator } => write!(
+                f,
+                "Operator `{}` expected a list, but got an integer",
+                operator
+            ),
+            TypeErrorKind::UnboundVariable { name } => write!(f, "Unbound variable: {}", name),
+            TypeErrorKind::UnknownFunction { name } => write!(f, "Unknown function: {}", name),
+            TypeErrorKind::WrongArguments { name, expected } => write!(
+                f,
+                "Wrong arguments for function {}, expected {}",
+                name, expected
+            ),
+        }
+    }
+}
+
+/// An error found by type checking, with the span of the ill-typed sub-expression.
+///
+/// # Example
+///
+/// ```
+/// # use rollkit::{TypeErrorKind, typecheck, parse};
+/// let input = "1 + (3d6)d6";
+/// let error = typecheck(&parse(input).unwrap()).unwrap_err();
+/// assert!(matches!(error.kind(), TypeErrorKind::IntegerExpected { operator: "d", .. }));
+/// assert_eq!(&input[error.span().into_range()], "(3d6)d6");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeError {
+    kind: TypeErrorKind,
+    span: Span,
+}
+
+impl TypeError {
+    /// Creates an error of the given kind, with the span of the ill-typed sub-expression.
+    pub fn new(kind: TypeErrorKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    /// Returns the kind of the error.
+    pub fn kind(&self) -> &TypeErrorKind {
+        &self.kind
+    }
+
+    /// Converts the error into its kind.
+    pub fn into_kind(self) -> TypeErrorKind {
+        self.kind
+    }
+
+    /// Returns the span of the ill-typed sub-expression.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl core::error::Error for TypeError {}
+
+/// The inferred type of an expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    /// The type is known statically.
+    Known(ValueType),
+    /// An integer or a weak list, e.g., the result of a user-defined function.
+    Summable,
+    /// Any type, e.g., the result of a conditional with a strong list and an integer as branches.
+    Any,
+}
+
+impl Type {
+    /// Returns whether the value can be a strong list.
+    fn can_be_strong(self) -> bool {
+        matches!(self, Type::Known(ValueType::StrongList) | Type::Any)
+    }
+
+    /// Returns the statically known type, if any.
+    fn known(self) -> Option<ValueType> {
+        match self {
+            Type::Known(ty) => Some(ty),
+            Type::Summable | Type::Any => None,
+        }
+    }
+}
+
+/// The type checker, inferring the types of expressions bottom-up.
+struct TypeChecker<'a> {
+    context: &'a EvalContext,
+    /// The types of the variables bound by `let`, innermost last.
+    scope: Vec<(String, Type)>,
+}
+
+impl TypeChecker<'_> {
+    /// Checks that an operand of `operator` is an integer. Weak lists are not summed up here.
+    fn expect_integer(ty: Type, operator: &'static str, span: Span) -> Result<(), TypeError> {
+        match ty {
+            Type::Known(found @ (ValueType::WeakList | ValueType::StrongList)) => Err(
+                TypeError::new(TypeErrorKind::IntegerExpected { operator, found }, span),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the type of arithmetic on operands, which is a strong list if any of them is.
+    fn elementwise(operands: &[Type]) -> Type {
+        if operands.contains(&Type::Known(ValueType::StrongList)) {
+            Type::Known(ValueType::StrongList)
+        } else if operands.contains(&Type::Any) {
+            Type::Any
+        } else {
+            Type::Known(ValueType::Integer)
+        }
+    }
+
+    /// Infers the type of a call of a built-in function.
+    ///
+    /// The function is evaluated on example values of the argument types, as the types of its
+    /// result only depend on the types of its arguments.
+    fn builtin_call(
+        builtin: &builtins::Builtin,
+        args: Vec<Type>,
+        span: Span,
+    ) -> Result<Type, TypeError> {
+        if args.contains(&Type::Any) {
+            return Ok(Type::Any);
+        }
+        let Some(args) = args
+            .into_iter()
+            .map(Type::known)
+            .collect::<Option<Vec<_>>>()
+        else {
+            return Ok(Type::Summable);
+        };
+        let args = args.into_iter().map(ValueType::example).collect();
+        match (builtin.func)(builtin, args, ArithmeticMode::Wrapping) {
+            Ok(value) => Ok(Type::Known(ValueType::of(&value))),
+            Err(
+                EvalErrorKind::WrongArguments { .. }
+                | EvalErrorKind::IntegerExpected
+                | EvalErrorKind::ListExpected,
+            ) => Err(TypeError::new(
+                TypeErrorKind::WrongArguments {
+                    name: builtin.name.to_string(),
+                    expected: builtin.signature,
+                },
+                span,
+            )),
+            // other errors depend on the values of the arguments
+            Err(_) => Ok(Type::Summable),
+        }
+    }
+
+    /// Infers the type of an expression.
+    fn check(&mut self, expr: &Expr) -> Result<Type, TypeError> {
+        let span = expr.span;
+        match &expr.kind {
+            ExprKind::Literal(Literal::Int(_)) => Ok(Type::Known(ValueType::Integer)),
+            ExprKind::Literal(Literal::List(_) | Literal::Range(_)) => {
+                Ok(Type::Known(ValueType::WeakList))
+            }
+            ExprKind::UnaryOp { expr, .. } => Ok(Self::elementwise(&[self.check(expr)?])),
+            ExprKind::BinaryOp { left, op, right } => {
+                let left = self.check(left)?;
+                let right = self.check(right)?;
+                let operator = op.to_str();
+                match op {
+                    BinaryOperator::DiceRoll => {
+                        Self::expect_integer(left, operator, span)?;
+                        Ok(Type::Known(ValueType::WeakList))
+                    }
+                    BinaryOperator::KeepHighest
+                    | BinaryOperator::KeepLowest
+                    | BinaryOperator::DropHighest
+                    | BinaryOperator::DropLowest => {
+                        if left == Type::Known(ValueType::Integer) {
+                            return Err(TypeError::new(
+                                TypeErrorKind::ListExpected { operator },
+                                span,
+                            ));
+                        }
+                        Self::expect_integer(right, operator, span)?;
+                        Ok(match left {
+                            // the kept elements of lists from user-defined functions are weak lists
+                            Type::Summable => Type::Known(ValueType::WeakList),
+                            left => left,
+                        })
+                    }
+                    _ => Ok(Self::elementwise(&[left, right])),
+                }
+            }
+            ExprKind::ModifiedDice {
+                count,
+                sides,
+                modifiers,
+            } => {
+                let count = self.check(count)?;
+                self.check(sides)?;
+                Self::expect_integer(count, "d", span)?;
+                let counts_successes = modifiers
+                    .iter()
+                    .any(|modifier| matches!(modifier, DiceModifier::CountSuccesses { .. }));
+                if counts_successes {
+                    Ok(Type::Known(ValueType::Integer))
+                } else {
+                    Ok(Type::Known(ValueType::WeakList))
+                }
+            }
+            ExprKind::FunctionCall { name, args } => {
+                let builtin = if self.context.functions.contains(name) {
+                    None
+                } else {
+                    let builtin = builtins::lookup(name).ok_or_else(|| {
+                        let name = name.clone();
+                        TypeError::new(TypeErrorKind::UnknownFunction { name }, span)
+                    })?;
+                    Some(builtin)
+                };
+                let args = args
+                    .iter()
+                    .map(|arg| self.check(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                match builtin {
+                    Some(builtin) => Self::builtin_call(builtin, args, span),
+                    None => Ok(Type::Summable),
+                }
+            }
+            ExprKind::Variable { name, host: true } => match self.context.variables.get(name) {
+                Some(Value::Integer(_)) => Ok(Type::Known(ValueType::Integer)),
+                Some(Value::List(_)) => Ok(Type::Known(ValueType::WeakList)),
+                None => Err(TypeError::new(
+                    TypeErrorKind::UnboundVariable {
+                        name: format!("@{}", name),
+                    },
+                    span,
+                )),
+            },
+            ExprKind::Variable { name, host: false } => {
+                let mut bindings = self.scope.iter().rev();
+                match bindings.find(|(bound, _)| bound == name) {
+                    Some((_, ty)) => Ok(*ty),
+                    None => Err(TypeError::new(
+                        TypeErrorKind::UnboundVariable { name: name.clone() },
+                        span,
+                    )),
+                }
+            }
+            ExprKind::Let { name, value, body } => {
+                let value = self.check(value)?;
+                self.scope.push((name.clone(), value));
+                let body = self.check(body);
+                self.scope.pop();
+                body
+            }
+            ExprKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if let Type::Known(found @ ValueType::StrongList) = self.check(condition)? {
+                    return Err(TypeError::new(
+                        TypeErrorKind::IntegerExpected {
+                            operator: "if",
+                            found,
+                        },
+                        span,
+                    ));
+                }
+                let then_branch = self.check(then_branch)?;
+                let else_branch = self.check(else_branch)?;
+                // otherwise, the type depends on the branch taken
+                Ok(if then_branch == else_branch {
+                    then_branch
+                } else if then_branch.can_be_strong() || else_branch.can_be_strong() {
+                    Type::Any
+                } else {
+                    Type::Summable
+                })
+            }
+            ExprKind::StrongList(expr) => Ok(match self.check(expr)? {
+                // integers become single-element weak lists
+                Type::Known(ValueType::Integer) => Type::Known(ValueType::WeakList),
+                Type::Known(_) => Type::Known(ValueType::StrongList),
+                Type::Summable | Type::Any => Type::Any,
+            }),
+        }
+    }
+}
+
+/// Type checks a RollKit expression with the default [evaluation context](EvalContext). See
+/// [`typecheck_with_context`] for details.
+///
+/// # Examples
+///
+/// ```
+/// # use rollkit::{ValueType, typecheck, parse};
+/// assert_eq!(typecheck(&parse("4d6kh3").unwrap()), Ok(Some(ValueType::WeakList)));
+/// assert_eq!(typecheck(&parse("{4d6} * 2").unwrap()), Ok(Some(ValueType::StrongList)));
+/// assert!(typecheck(&parse("5kh2").unwrap()).is_err());
+/// ```
+pub fn typecheck(expr: &Expr) -> Result<Option<ValueType>, TypeError> {
+    typecheck_with_context(expr, &EvalContext::default())
+}
+
+/// Type checks a RollKit expression with the given [evaluation context](EvalContext), and returns
+/// the type of its result.
+///
+/// Type checking classifies each sub-expression as an integer, weak list or strong list without
+/// rolling any dice, and rejects expressions which would fail to evaluate because of a type
+/// mismatch, e.g., `(3d6)d6`, which rolls a list of dice, or `5kh2`, which keeps elements of an
+/// integer. Errors that depend on the values, e.g., `1 / 0` or `4d6kh5`, are left to the
+/// evaluation. Expressions that pass type checking evaluate to a value of the returned type, or
+/// fail with such an error.
+///
+/// The results of user-defined functions can be integers or weak lists, and the results of
+/// conditionals with branches of different types depend on the branch taken, so the type is
+/// `None` if it depends on them, and the operands depending on them are assumed to be
+/// well-typed.
+///
+/// # Examples
+///
+/// ```
+/// # use rollkit::{EvalContext, FunctionRegistry, TypeErrorKind, Value, typecheck_with_context, parse};
+/// let mut functions = FunctionRegistry::new();
+/// functions.register("pool", |args| Ok(Value::List(vec![0; args[0].sum() as usize])));
+/// let context = EvalContext { functions, ..Default::default() };
+///
+/// assert_eq!(typecheck_with_context(&parse("pool(3)kh1").unwrap(), &context).map(|_| ()), Ok(()));
+/// let error = typecheck_with_context(&parse("len(pool(3) + 1)").unwrap(), &context).unwrap_err();
+/// assert!(matches!(error.kind(), TypeErrorKind::WrongArguments { .. }));
+/// ```
+pub fn typecheck_with_context(
+    expr: &Expr,
+    context: &EvalContext,
+) -> Result<Option<ValueType>, TypeError> {
+    let mut checker = TypeChecker {
+        context,
+        scope: Vec::new(),
+    };
+    checker.check(expr).map(Type::known)
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::{EvalError, Value, eval_with, parse};
+
+    #[test]
+    fn test_typecheck() {
+        let cases = [
+            ("1 + 2", Some(ValueType::Integer)),
+            ("3d6", Some(ValueType::WeakList)),
+            ("{3d6}", Some(ValueType::StrongList)),
+            ("{3d6} * 2", Some(ValueType::StrongList)),
+            ("-{3d6}", Some(ValueType::StrongList)),
+            ("{3d6} + [1, 3]", Some(ValueType::StrongList)),
+            ("-3d6", Some(ValueType::Integer)),
+            ("{5}", Some(ValueType::WeakList)),
+            ("{{1, 2}}kh1", Some(ValueType::StrongList)),
+            ("4d6kh3", Some(ValueType::WeakList)),
+            ("3d6>=5", Some(ValueType::Integer)),
+            ("3d6!r1", Some(ValueType::WeakList)),
+            ("(1 + 1d4)d{1, 2}", Some(ValueType::WeakList)),
+            ("1d(2d6)", Some(ValueType::WeakList)),
+            ("max(2d20)", Some(ValueType::Integer)),
+            ("sort({4d6})", Some(ValueType::StrongList)),
+            ("abs({1, -2})", Some(ValueType::Integer)),
+            ("4d6kh5", Some(ValueType::WeakList)),
+            ("avg(0d6)", Some(ValueType::Integer)),
+            ("let x = {3d6}; x * 2", Some(ValueType::StrongList)),
+            ("let x = 3d6; let x = x + 1; {x}", Some(ValueType::WeakList)),
+            ("if 1d2 == 2 then 1d6 else 2", None),
+            ("if 3d6 then {2d6} else {1d4}", Some(ValueType::StrongList)),
+            (
+                "(if 1d2 > 1 then 1 else {2}) * {{1, 2}}",
+                Some(ValueType::StrongList),
+            ),
+            ("(if 1d2 > 1 then 1 else {2}) + 1", Some(ValueType::Integer)),
+            ("2 * (if 1d2 == 1 then 1 else {1d6})", None),
+            ("{if 1d2 == 1 then 1 else 2d6}", None),
+        ];
+        for (input, expected) in cases {
+            let expr = parse(input).unwrap();
+            assert_eq!(typecheck(&expr), Ok(expected), "Input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_type_errors() {
+        let integer_expected = |operator, found| TypeErrorKind::IntegerExpected { operator, found };
+        let cases = [
+            (
+                "(3d6)d6",
+                "(3d6)d6",
+                integer_expected("d", ValueType::WeakList),
+            ),
+            (
+                "2 * ({1d4}d6!)",
+                "{1d4}d6!",
+                integer_expected("d", ValueType::StrongList),
+            ),
+            (
+                "1 + 5kh2",
+                "5kh2",
+                TypeErrorKind::ListExpected { operator: "kh" },
+            ),
+            (
+                "4d6kh[1, 2]",
+                "4d6kh[1, 2]",
+                integer_expected("kh", ValueType::WeakList),
+            ),
+            (
+                "len(1 + 2)",
+                "len(1 + 2)",
+                TypeErrorKind::WrongArguments {
+                    name: "len".to_string(),
+                    expected: builtins::lookup("len").unwrap().signature,
+                },
+            ),
+            (
+                "let x = 5; 1 + x kh 1",
+                "x kh 1",
+                TypeErrorKind::ListExpected { operator: "kh" },
+            ),
+            (
+                "(let y = 1; y) + y",
+                "y",
+                TypeErrorKind::UnboundVariable {
+                    name: "y".to_string(),
+                },
+            ),
+            (
+                "1d@sides",
+                "@sides",
+                TypeErrorKind::UnboundVariable {
+                    name: "@sides".to_string(),
+                },
+            ),
+            (
+                "1 + (if {1d2} then 1 else 2)",
+                "if {1d2} then 1 else 2",
+                integer_expected("if", ValueType::StrongList),
+            ),
+            (
+                "if 1 then 2 else 3kh1",
+                "3kh1",
+                TypeErrorKind::ListExpected { operator: "kh" },
+            ),
+            (
+                "frobnicate(5kh2)",
+                "frobnicate(5kh2)",
+                TypeErrorKind::UnknownFunction {
+                    name: "frobnicate".to_string(),
+                },
+            ),
+        ];
+        for (input, span, expected) in cases {
+            let error = typecheck(&parse(input).unwrap()).unwrap_err();
+            assert_eq!(error.kind(), &expected, "Input: {}", input);
+            assert_eq!(&input[error.span().into_range()], span, "Input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_matches_eval() {
+        let inputs = [
+            "{1d2} * [1, 2]",
+            "(1d2)d6",
+            "{1, 2}kh(1d2)",
+            "max(1, {{1, 2}})",
+            "count({1d6}, 2)",
+            "reverse(5)",
+            "clamp(3d6, 1, 10)",
+            "1d{1d6}",
+            "{1d6 + 1}dl1",
+            "if {1d2} then 1 else 2",
+            "if 1d2 == 1 then 2d6 else 1d2d6",
+            "2 * (if 1d2 == 1 then 1 else {1d6})",
+        ];
+        for input in inputs {
+            let expr = parse(input).unwrap();
+            let ty = typecheck(&expr);
+            for seed in 0..10 {
+                let result = eval_with(&expr, &mut StdRng::seed_from_u64(seed));
+                match (&ty, result) {
+                    (Ok(None), Ok(_)) => {}
+                    (Ok(Some(ty)), Ok(value)) => assert_eq!(
+                        *ty == ValueType::Integer,
+                        matches!(value, Value::Integer(_)),
+                        "Input: {}",
+                        input
+                    ),
+                    (Err(error), Err(eval_error)) => {
+                        assert_eq!(Some(error.span()), eval_error.span(), "Input: {}", input)
+                    }
+                    (ty, result) => panic!("Input: {}, {:?} but {:?}", input, ty, result),
+                }
+            }
+        }
+        assert!(matches!(
+            eval_with(&parse("(3d6)d6").unwrap(), &mut StdRng::seed_from_u64(0))
+                .map_err(EvalError::into_kind),
+            Err(EvalErrorKind::IntegerExpected)
+        ));
+    }
+}
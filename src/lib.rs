@@ -5,17 +5,30 @@
 #![warn(clippy::print_stderr)]
 #![warn(clippy::print_stdout)]
 
+pub mod analysis;
 mod ast;
 mod eval;
 mod parser;
+mod render;
+mod roll;
 
 /// Module containing all parsing-related functionality.
 pub mod parsing {
     pub use crate::{ast::*, parser::*};
 }
 
-pub use eval::{EvalError, Value, eval_with};
+pub use eval::{
+    ArithmeticMode, CallContext, DieRoll, EvalContext, EvalError, EvalErrorKind, EvalLimits,
+    EvalPlan, FunctionRegistry, TraceNode, TypeError, TypeErrorKind, Value, ValueType,
+    eval_traced_with, eval_traced_with_context, eval_with, eval_with_context, is_deterministic,
+    is_deterministic_with_context, optimize, optimize_with_context, typecheck,
+    typecheck_with_context,
+};
 pub use parser::parse;
+pub use render::{RenderStyle, render_trace};
+pub use roll::{RollError, roll_with, roll_with_context};
 
 #[cfg(feature = "std")]
-pub use eval::eval;
+pub use eval::{eval, eval_traced};
+#[cfg(feature = "std")]
+pub use roll::roll;
@@ -3,16 +3,27 @@ extern crate alloc;
 #[cfg(not(feature = "std"))]
 use alloc::{boxed::Box, format, string::ToString, vec, vec::Vec};
 
-use crate::ast::{BinaryOperator, Expr, Literal, RangeLiteral};
+use crate::ast::{
+    BinaryOperator, CompareOp, ComparePoint, DiceModifier, ExplodeKind, Expr, ExprKind, Literal,
+    RangeLiteral, Span, UnaryOperator,
+};
 
 use chumsky::{
-    pratt::{Associativity, infix, left, right},
+    input::MapExtra,
+    pratt::{Associativity, infix, left, prefix, right},
     prelude::*,
 };
 
+mod error;
+
+pub use error::{Expected, ParseError};
+
 type ParserInput<'a> = &'a str;
 type ParserError<'a> = extra::Err<Rich<'a, char>>;
 
+/// Keywords, which can't be used as variable names.
+const KEYWORDS: &[&str] = &["else", "if", "let", "then"];
+
 /// Parse a RollKit expression from a string input.
 ///
 /// For the syntax and supported expressions, see the [crate-level documentation](crate).
@@ -21,23 +32,30 @@ type ParserError<'a> = extra::Err<Rich<'a, char>>;
 ///
 /// ```
 /// # use rollkit::{parse, parsing::*};
-/// assert_eq!(parse("2d6 + 3"), Ok(Expr::BinaryOp {
-///     left: Box::new(Expr::BinaryOp {
-///         left: Box::new(Expr::Literal(Literal::Int(2))),
+/// let expr = parse("2d6 + 3").unwrap();
+/// // spans are ignored when comparing expressions
+/// assert_eq!(expr, Expr::from(ExprKind::BinaryOp {
+///     left: Box::new(Expr::from(ExprKind::BinaryOp {
+///         left: Box::new(Expr::from(ExprKind::Literal(Literal::Int(2)))),
 ///         op: BinaryOperator::DiceRoll,
-///         right: Box::new(Expr::Literal(Literal::Int(6))),
-///     }),
+///         right: Box::new(Expr::from(ExprKind::Literal(Literal::Int(6)))),
+///     })),
 ///     op: BinaryOperator::Addition,
-///     right: Box::new(Expr::Literal(Literal::Int(3))),
+///     right: Box::new(Expr::from(ExprKind::Literal(Literal::Int(3)))),
 /// }));
+/// assert_eq!(expr.span, Span::new(0, 7));
 ///
 /// assert!(parse("4d{1,2,3}kh2").is_ok());
 /// assert!(parse("[1, 10, 2] + 5").is_ok());
 /// assert!(parse("max(3d6)").is_ok());
+/// assert!(parse("let atk = 1d20 + @str_mod; atk >= 15").is_ok());
 /// assert!(parse("1+").is_err());
 /// ```
-pub fn parse(input: &str) -> Result<Expr, Vec<Rich<'_, char>>> {
-    parser().parse(input).into_result()
+pub fn parse(input: &str) -> Result<Expr, Vec<ParseError>> {
+    parser()
+        .parse(input)
+        .into_result()
+        .map_err(|errors| errors.into_iter().map(ParseError::from_rich).collect())
 }
 
 /// Main parser for the RollKit expression.
@@ -66,30 +84,135 @@ fn integer_parser<'a>() -> impl Parser<'a, ParserInput<'a>, i64, ParserError<'a>
                 }
             }
         })
-        .padded()
+        .labelled("integer")
+}
+
+/// Creates a parser for unsigned integer literals with overflow handling. Used where a sign would
+/// be ambiguous, e.g., in expressions, where `-` is parsed as an operator.
+fn unsigned_integer_parser<'a>() -> impl Parser<'a, ParserInput<'a>, i64, ParserError<'a>> + Clone {
+    text::int(10)
+        .validate(|num: &str, extra, emitter| match num.parse::<i64>() {
+            Ok(val) => val,
+            Err(e) => {
+                emitter.emit(Rich::custom(
+                    extra.span(),
+                    format!("illegal integer literal: {}", e),
+                ));
+                0 // Return a default value
+            }
+        })
         .labelled("integer")
 }
 
 /// Creates a parser for range list literals.
 fn range_list_parser<'a>() -> impl Parser<'a, ParserInput<'a>, Literal, ParserError<'a>> + Clone {
-    let integer = integer_parser();
+    let integer = integer_parser().padded();
 
     // Parse range list literal: [start, end] or [start, end, step]
     integer
         .clone()
         .then_ignore(just(',').padded())
         .then(integer.clone())
-        .then(just(',').padded().ignore_then(integer.clone()).or_not())
-        .delimited_by(just('[').padded(), just(']').padded())
+        .then(
+            just(',')
+                .padded()
+                .ignore_then(integer.clone().validate(|step, extra, emitter| {
+                    if step == 0 {
+                        emitter.emit(Rich::custom(
+                            extra.span(),
+                            "the step of a range list cannot be zero",
+                        ));
+                    }
+                    step
+                }))
+                .or_not(),
+        )
+        .delimited_by(just('[').padded(), just(']'))
         .map(|((start, end), step)| Literal::Range(RangeLiteral { start, end, step }))
         .labelled("range list")
 }
 
+/// Creates a parser for compare points, e.g., `>=5`. If `bare` is set, a bare unsigned integer is
+/// also accepted as an equality compare point, e.g., `6` for `=6`.
+fn compare_point_parser<'a>(
+    bare: bool,
+) -> impl Parser<'a, ParserInput<'a>, ComparePoint, ParserError<'a>> + Clone {
+    let op = choice((
+        just("<=").to(CompareOp::LessEqual),
+        just(">=").to(CompareOp::GreaterEqual),
+        just("<").to(CompareOp::LessThan),
+        just(">").to(CompareOp::GreaterThan),
+        just("=").to(CompareOp::Equal),
+    ));
+
+    let explicit = op
+        .then(integer_parser())
+        .map(|(op, value)| ComparePoint { op, value });
+    let bare_value = unsigned_integer_parser()
+        .map(|value| ComparePoint {
+            op: CompareOp::Equal,
+            value,
+        })
+        .filter(move |_| bare);
+
+    explicit.or(bare_value).labelled("compare point")
+}
+
+/// Creates a parser for dice modifiers, e.g., `!`, `!>5`, `ro<=2` or `>=5f1`.
+fn dice_modifier_parser<'a>()
+-> impl Parser<'a, ParserInput<'a>, DiceModifier, ParserError<'a>> + Clone {
+    // Explode: !, !! or !p, optionally followed by a compare point
+    let explode_kind = choice((
+        just(ExplodeKind::Compounding.to_str()).to(ExplodeKind::Compounding),
+        just(ExplodeKind::Penetrating.to_str()).to(ExplodeKind::Penetrating),
+        just(ExplodeKind::Standard.to_str()).to(ExplodeKind::Standard),
+    ));
+
+    let explode = explode_kind
+        .then(compare_point_parser(true).or_not())
+        .map(|(kind, condition)| DiceModifier::Explode { kind, condition });
+
+    // Reroll: r or ro, followed by a compare point
+    let reroll = just('r')
+        .ignore_then(just('o').or_not())
+        .then(compare_point_parser(true))
+        .map(|(once, condition)| DiceModifier::Reroll {
+            once: once.is_some(),
+            condition,
+        });
+
+    // Count successes: a compare point, optionally followed by f and a compare point for failures
+    let count_successes = compare_point_parser(false)
+        .then(just('f').ignore_then(compare_point_parser(true)).or_not())
+        .map(|(success, failure)| DiceModifier::CountSuccesses { success, failure });
+
+    choice((explode, reroll, count_successes)).labelled("dice modifier")
+}
+
+/// Creates a parser for variable names, i.e., identifiers other than keywords.
+fn variable_name_parser<'a>() -> impl Parser<'a, ParserInput<'a>, &'a str, ParserError<'a>> + Clone
+{
+    text::ascii::ident()
+        .filter(|name: &&str| !KEYWORDS.contains(name))
+        .labelled("variable name")
+}
+
+/// Converts a span of the parser into a [`Span`].
+fn to_span(span: SimpleSpan) -> Span {
+    Span::new(span.start, span.end)
+}
+
 /// Creates a parser for RollKit expressions.
 fn expression_parser<'a>() -> impl Parser<'a, ParserInput<'a>, Expr, ParserError<'a>> + Clone {
     recursive(|expr| {
-        // Parse integer literals (positive and negative)
-        let integer = integer_parser();
+        // Parse integer literals, negative integers are handled by the negation operator, except
+        // for the lowest integer, whose magnitude doesn't fit in an `i64`
+        let lowest_integer = just('-')
+            .padded()
+            .ignore_then(text::int(10))
+            .filter(|num: &&str| *num == "9223372036854775808")
+            .to(i64::MIN);
+        let integer = lowest_integer.or(unsigned_integer_parser());
 
         // Parse range list literal: [start, end] or [start, end, step]
         let range_list = range_list_parser();
@@ -102,76 +225,231 @@ fn expression_parser<'a>() -> impl Parser<'a, ParserInput<'a>, Expr, ParserError
                     .separated_by(just(',').padded())
                     .allow_trailing()
                     .collect::<Vec<Expr>>()
-                    .delimited_by(just('(').padded(), just(')').padded()),
+                    .delimited_by(just('(').padded(), just(')')),
             )
-            .map(|(name, args): (&str, Vec<Expr>)| Expr::FunctionCall {
-                name: name.to_string(),
-                args,
+            .map_with(|(name, args): (&str, Vec<Expr>), extra| {
+                Expr::new(
+                    ExprKind::FunctionCall {
+                        name: name.to_string(),
+                        args,
+                    },
+                    to_span(extra.span()),
+                )
             })
             .labelled("function call");
 
+        // Variable: a name not followed by `(`, or a host variable prefixed with `@`
+        let variable = just('@')
+            .or_not()
+            .then(variable_name_parser())
+            .map_with(|(at, name): (Option<char>, &str), extra| {
+                Expr::new(
+                    ExprKind::Variable {
+                        name: name.to_string(),
+                        host: at.is_some(),
+                    },
+                    to_span(extra.span()),
+                )
+            })
+            .labelled("variable");
+
         // Explicit list literal: {1, 2, 3} or {{...}} for strong lists
         let list = expr
             .clone()
             .separated_by(just(',').padded())
             .allow_trailing()
             .collect::<Vec<Expr>>()
-            .delimited_by(just('{').padded(), just('}').padded())
+            .delimited_by(just('{').padded(), just('}'))
             .validate(|exprs, extra, emitter| {
                 // Check if all expressions are integer literals to create a List
                 let mut int_values = Vec::new();
                 let mut all_ints = true;
 
                 for e in &exprs {
-                    if let Expr::Literal(Literal::Int(val)) = e {
-                        int_values.push(*val);
+                    if let ExprKind::Literal(Literal::Int(val)) = e.kind {
+                        int_values.push(val);
                     } else {
                         all_ints = false;
                         break;
                     }
                 }
 
-                if all_ints && !exprs.is_empty() {
-                    Expr::Literal(Literal::List(int_values))
+                let kind = if all_ints && !exprs.is_empty() {
+                    ExprKind::Literal(Literal::List(int_values))
                 } else if exprs.len() == 1 {
                     // Single element could be making a strong list
-                    Expr::StrongList(Box::new(exprs[0].clone()))
+                    ExprKind::StrongList(Box::new(exprs[0].clone()))
                 } else {
                     emitter.emit(Rich::custom(
                         extra.span(),
                         "things inside braces must be all integers for a list, or a single expression for a strong list",
                     ));
-                    Expr::Literal(Literal::List(vec![])) // Default empty list on error
-                }
+                    ExprKind::Literal(Literal::List(vec![])) // Default empty list on error
+                };
+                Expr::new(kind, to_span(extra.span()))
+            });
+
+        // Let-binding: let name = value; body. The body extends as far as possible.
+        let binding = text::ascii::keyword("let")
+            .ignore_then(variable_name_parser().padded())
+            .then_ignore(just('=').padded())
+            .then(expr.clone())
+            .then_ignore(just(';'))
+            .then(expr.clone())
+            .map_with(|((name, value), body): ((&str, Expr), Expr), extra| {
+                Expr::new(
+                    ExprKind::Let {
+                        name: name.to_string(),
+                        value: Box::new(value),
+                        body: Box::new(body),
+                    },
+                    to_span(extra.span()),
+                )
+            })
+            .labelled("let-binding");
+
+        // Conditional: if condition then a else b. The else branch extends as far as possible.
+        let conditional = text::ascii::keyword("if")
+            .ignore_then(expr.clone())
+            .then_ignore(text::ascii::keyword("then"))
+            .then(expr.clone())
+            .then_ignore(text::ascii::keyword("else"))
+            .then(expr.clone())
+            .map_with(
+                |((condition, then_branch), else_branch): ((Expr, Expr), Expr), extra| {
+                    Expr::new(
+                        ExprKind::If {
+                            condition: Box::new(condition),
+                            then_branch: Box::new(then_branch),
+                            else_branch: Box::new(else_branch),
+                        },
+                        to_span(extra.span()),
+                    )
+                },
+            )
+            .labelled("conditional");
+
+        // Let-bindings and conditionals extend as far as possible, so as operands they must be
+        // parenthesized
+        let unparenthesized = binding
+            .clone()
+            .or(conditional.clone())
+            .validate(|expr: Expr, extra, emitter| {
+                emitter.emit(Rich::custom(
+                    extra.span(),
+                    "let-bindings and conditionals must be parenthesized when used as operands",
+                ));
+                expr
             });
 
-        // Atom: integer, range list, explicit list, function call, or parenthesized expression
+        // Atom: integer, range list, explicit list, function call, variable, or parenthesized
+        // expression.
+        // Atoms don't consume trailing whitespace, so that dice modifiers can be required to
+        // directly follow the dice.
         let atom = choice((
+            unparenthesized,
             function_call,
-            range_list.map(Expr::Literal),
+            variable,
+            range_list.map_with(|range, extra| {
+                Expr::new(ExprKind::Literal(range), to_span(extra.span()))
+            }),
             list,
-            integer.clone().map(|i| Expr::Literal(Literal::Int(i))),
+            integer.clone().map_with(|i, extra| {
+                Expr::new(ExprKind::Literal(Literal::Int(i)), to_span(extra.span()))
+            }),
             expr.clone()
-                .delimited_by(just('(').padded(), just(')').padded()),
-        ))
-        .padded();
+                .delimited_by(just('(').padded(), just(')')),
+        ));
+
+        // Dice roll: count d sides, optionally followed by modifiers directly after the sides,
+        // e.g. `3d6!`. Dice rolls are right associative and bind tighter than any other operator.
+        // A `d` directly followed by `l` or `h` is a drop operator, not sides named by a variable.
+        let dice = recursive(|dice| {
+            atom.clone()
+                .then(
+                    just(BinaryOperator::DiceRoll.to_str())
+                        .then_ignore(one_of("lh").not())
+                        .padded()
+                        .ignore_then(dice)
+                        .then(dice_modifier_parser().repeated().collect::<Vec<_>>())
+                        .or_not(),
+                )
+                .map_with(|(count, roll): (Expr, Option<(Expr, Vec<DiceModifier>)>), extra| {
+                    let kind = match roll {
+                        None => return count,
+                        Some((sides, modifiers)) if modifiers.is_empty() => ExprKind::BinaryOp {
+                            left: Box::new(count),
+                            op: BinaryOperator::DiceRoll,
+                            right: Box::new(sides),
+                        },
+                        Some((sides, modifiers)) => ExprKind::ModifiedDice {
+                            count: Box::new(count),
+                            sides: Box::new(sides),
+                            modifiers,
+                        },
+                    };
+                    Expr::new(kind, to_span(extra.span()))
+                })
+        });
 
         let binary_op_to_pratt = |op: BinaryOperator, accos: fn(u16) -> Associativity| {
-            // let v = just(op.to_str()).padded();
-            infix(accos(op.precedence()), just(op.to_str()).padded(), move |left: Expr, _, right: Expr, _| Expr::BinaryOp {
-                left: Box::new(left),
-                op,
-                right: Box::new(right),
+            infix(accos(op.precedence()), just(op.to_str()).padded(), move |left: Expr, _, right: Expr, extra: &mut MapExtra<'a, '_, _, _>| {
+                Expr::new(
+                    ExprKind::BinaryOp {
+                        left: Box::new(left),
+                        op,
+                        right: Box::new(right),
+                    },
+                    to_span(extra.span()),
+                )
+            })
+        };
+
+        let unary_op_to_pratt = |op: UnaryOperator| {
+            // the sign of the lowest integer is part of the literal, not an operator
+            let op_parser = just(op.to_str()).padded().and_is(lowest_integer.not());
+            prefix(op.precedence(), op_parser, move |_, expr: Expr, extra: &mut MapExtra<'a, '_, _, _>| {
+                let kind = match (op, expr.kind) {
+                    // fold signs into integer literals, so that e.g. `-1` is a literal
+                    (UnaryOperator::Negation, ExprKind::Literal(Literal::Int(n))) if n != i64::MIN => ExprKind::Literal(Literal::Int(-n)),
+                    (UnaryOperator::Plus, kind @ ExprKind::Literal(Literal::Int(_))) => kind,
+                    (op, kind) => ExprKind::UnaryOp {
+                        op,
+                        expr: Box::new(Expr::new(kind, expr.span)),
+                    },
+                };
+                Expr::new(kind, to_span(extra.span()))
             })
         };
 
-        atom.clone().pratt((
-            binary_op_to_pratt(BinaryOperator::DiceRoll, right),
+        let arithmetic = dice.pratt((
+            unary_op_to_pratt(UnaryOperator::Negation),
+            unary_op_to_pratt(UnaryOperator::Plus),
             binary_op_to_pratt(BinaryOperator::KeepHighest, left),
             binary_op_to_pratt(BinaryOperator::KeepLowest, left),
             binary_op_to_pratt(BinaryOperator::DropHighest, left),
             binary_op_to_pratt(BinaryOperator::DropLowest, left),
+            // `**` must be tried before `*`
+            infix(
+                right(BinaryOperator::Exponentiation.precedence()),
+                just("**").or(just("^")).padded(),
+                |left: Expr, _, right: Expr, extra: &mut MapExtra<'a, '_, _, _>| {
+                    Expr::new(
+                        ExprKind::BinaryOp {
+                            left: Box::new(left),
+                            op: BinaryOperator::Exponentiation,
+                            right: Box::new(right),
+                        },
+                        to_span(extra.span()),
+                    )
+                },
+            ),
             binary_op_to_pratt(BinaryOperator::Multiplication, left),
+            // `//` and `/^` must be tried before `/`
+            binary_op_to_pratt(BinaryOperator::FloorDivision, left),
+            binary_op_to_pratt(BinaryOperator::CeilDivision, left),
+            binary_op_to_pratt(BinaryOperator::Division, left),
+            binary_op_to_pratt(BinaryOperator::Modulo, left),
             binary_op_to_pratt(BinaryOperator::Addition, left),
             binary_op_to_pratt(BinaryOperator::Subtraction, left),
             binary_op_to_pratt(BinaryOperator::Equal, left),
@@ -180,7 +458,9 @@ fn expression_parser<'a>() -> impl Parser<'a, ParserInput<'a>, Expr, ParserError
             binary_op_to_pratt(BinaryOperator::LessEqual, left),
             binary_op_to_pratt(BinaryOperator::GreaterThan, left),
             binary_op_to_pratt(BinaryOperator::GreaterEqual, left),
-        )).padded()
+        ));
+
+        choice((binding, conditional, arithmetic)).padded()
     })
     .then_ignore(end())
 }
@@ -222,6 +502,7 @@ mod test {
             ("[1]", Err(())),          // Incomplete range
             ("[1, 2, 3, 4]", Err(())), // Too many elements
             ("[a, b]", Err(())),       // Invalid integers
+            ("[1, 10, 0]", Err(())),   // Zero step
         ];
 
         for (input, expected) in cases {
@@ -238,4 +519,138 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn test_expression_parsing() {
+        let cases = vec![
+            ("2d6 + 3", Ok("((2 d 6) + 3)")),
+            ("4d6kh3", Ok("((4 d 6) kh 3)")),
+            ("2d3d6", Ok("(2 d (3 d 6))")),
+            ("max( 3d6 )", Ok("max((3 d 6))")),
+            ("{ 1, 2 } + [1, 3] ", Ok("({1, 2} + [1, 3])")),
+            ("3d6!", Ok("(3 d 6!)")),
+            ("3d6!>=5 + 1", Ok("((3 d 6!>=5) + 1)")),
+            ("3d6!6", Ok("(3 d 6!=6)")),
+            ("3d6!=1!=6", Ok("(3 d 6!=1!=6)")),
+            ("3d6!-1", Ok("((3 d 6!) - 1)")),
+            ("3d6!kh2", Ok("((3 d 6!) kh 2)")),
+            ("3d6!!", Ok("(3 d 6!!)")),
+            ("3d6!!>4", Ok("(3 d 6!!>4)")),
+            ("3d6!p", Ok("(3 d 6!p)")),
+            ("3d6!p5", Ok("(3 d 6!p=5)")),
+            ("2d6ro<=2", Ok("(2 d 6ro<=2)")),
+            ("4d6r1r2!", Ok("(4 d 6r=1r=2!)")),
+            ("4d6r", Err(())),
+            ("10d10>=8", Ok("(10 d 10>=8)")),
+            ("8d6>=5f1 + 1", Ok("((8 d 6>=5f=1) + 1)")),
+            ("8d6=6f<2", Ok("(8 d 6=6f<2)")),
+            ("10d10!>=8", Ok("(10 d 10!>=8)")),
+            ("10d10 >= 8", Ok("((10 d 10) >= 8)")),
+            ("3d6==6", Ok("((3 d 6) == 6)")),
+            ("10d10>=8f", Err(())),
+            ("(1d20 + 5) / 2", Ok("(((1 d 20) + 5) / 2)")),
+            ("7 // 2 /^ 3 % 4 * 5", Ok("((((7 // 2) /^ 3) % 4) * 5)")),
+            ("1 + 6 / 2", Ok("(1 + (6 / 2))")),
+            ("-5", Ok("-5")),
+            ("- 5 + +3", Ok("(-5 + 3)")),
+            ("-1d4", Ok("(-(1 d 4))")),
+            ("-(2d6)", Ok("(-(2 d 6))")),
+            ("10 - -1d6", Ok("(10 - (-(1 d 6)))")),
+            ("-4d6kh3 * 2", Ok("((-((4 d 6) kh 3)) * 2)")),
+            ("+{3d6}", Ok("(+{(3 d 6)})")),
+            ("--5", Ok("5")),
+            ("-9223372036854775808", Ok("-9223372036854775808")),
+            ("1 - -9223372036854775808", Ok("(1 - -9223372036854775808)")),
+            ("{-9223372036854775808, 1}", Ok("{-9223372036854775808, 1}")),
+            ("--9223372036854775808", Ok("(--9223372036854775808)")),
+            ("9223372036854775808", Err(())),
+            ("-9223372036854775809", Err(())),
+            ("2 ** 3 ^ 2", Ok("(2 ^ (3 ^ 2))")),
+            ("2 * 3 ^ 2", Ok("(2 * (3 ^ 2))")),
+            ("-2^2", Ok("(-(2 ^ 2))")),
+            ("2^-1", Ok("(2 ^ -1)")),
+            ("3d6^2", Ok("((3 d 6) ^ 2)")),
+            ("2^(1d4)", Ok("(2 ^ (1 d 4))")),
+            ("4d6kh3**2", Ok("(((4 d 6) kh 3) ^ 2)")),
+            ("2 ^", Err(())),
+            ("{-1, 2}", Ok("{-1, 2}")),
+            ("3d6!>=-1", Ok("(3 d 6!>=-1)")),
+            ("3 d (1d6)!", Ok("(3 d (1 d 6)!)")),
+            // modifiers must directly follow the dice
+            ("3d6 != 6", Ok("((3 d 6) != 6)")),
+            ("3d6 !", Err(())),
+            ("(3d6)!", Err(())),
+            ("let x = 1d20; x + 1", Ok("(let x = (1 d 20); (x + 1))")),
+            ("let x=1;let y=x;y", Ok("(let x = 1; (let y = x; y))")),
+            ("(let x = 2; x) * @mod", Ok("((let x = 2; x) * @mod)")),
+            ("x d 6 + max(x)", Ok("((x d 6) + max(x))")),
+            ("1d@sides kh 1", Ok("((1 d @sides) kh 1)")),
+            ("4d6dl1 + 3d6dhx", Ok("(((4 d 6) dl 1) + ((3 d 6) dh x))")),
+            ("1d lvl + 1dx", Ok("((1 d lvl) + (1 d x))")),
+            (
+                "if 1d20 == 20 then 2d8 else 1d8",
+                Ok("(if ((1 d 20) == 20) then (2 d 8) else (1 d 8))"),
+            ),
+            (
+                "if x < 5 then 1 else if x < 10 then 2 else 3",
+                Ok("(if (x < 5) then 1 else (if (x < 10) then 2 else 3))"),
+            ),
+            (
+                "1 + (if 1d2 > 1 then 3 else 4) * 2",
+                Ok("(1 + ((if ((1 d 2) > 1) then 3 else 4) * 2))"),
+            ),
+            ("iffy + elsewhere", Ok("(iffy + elsewhere)")),
+            ("if x then 1", Err(())),
+            ("1 + if x then 1 else 2", Err(())),
+            ("let if = 1; 2", Err(())),
+            ("let let = 1; 2", Err(())),
+            ("let x = 1", Err(())),
+            ("1 + let x = 1; x", Err(())),
+            ("@ x", Err(())),
+        ];
+
+        for (input, expected) in cases {
+            let result = parse(input).map(|expr| expr.format_inline());
+            match (&result, expected) {
+                (Ok(val), Ok(exp)) => assert_eq!(val, exp, "Input: {}", input),
+                (Err(_), Err(())) => {} // Expected error
+                _ => panic!("Unexpected result for input {}: {:?}", input, result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_spans() {
+        let input = "1 + (4d6kh3 * max(2, 3))";
+        let expr = parse(input).unwrap();
+        let slice = |expr: &Expr| &input[expr.span.into_range()];
+        assert_eq!(slice(&expr), input);
+
+        let ExprKind::BinaryOp { left, right, .. } = &expr.kind else {
+            panic!("Unexpected expression: {:?}", expr);
+        };
+        assert_eq!(slice(left), "1");
+        assert_eq!(slice(right), "4d6kh3 * max(2, 3)");
+
+        let ExprKind::BinaryOp { left, right, .. } = &right.kind else {
+            panic!("Unexpected expression: {:?}", right);
+        };
+        assert_eq!(slice(left), "4d6kh3");
+        assert_eq!(slice(right), "max(2, 3)");
+    }
+
+    #[test]
+    fn test_unparenthesized_operands() {
+        for input in ["1d6 + if 1 then 2 else 3", "2 * let x = 1; x"] {
+            let errors = parse(input).unwrap_err();
+            assert_eq!(errors.len(), 1, "Input: {}", input);
+            assert!(
+                errors[0].message().contains("parenthesized"),
+                "Input: {}",
+                input
+            );
+            let operand = input.split_once(['+', '*']).unwrap().1.trim();
+            assert_eq!(&input[errors[0].span().into_range()], operand);
+        }
+    }
 }
@@ -0,0 +1,136 @@
+//! Errors reported by the parser.
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use core::fmt;
+
+use chumsky::error::{Rich, RichPattern, RichReason};
+
+use crate::ast::Span;
+
+/// An error encountered while parsing an expression.
+///
+/// # Example
+///
+/// ```
+/// # use rollkit::{parse, parsing::*};
+/// let errors = parse("2d6 ]").unwrap_err();
+/// assert_eq!(errors.len(), 1);
+/// assert_eq!(errors[0].span(), Span::new(4, 5));
+/// assert_eq!(errors[0].found(), Some(']'));
+/// assert!(errors[0].expected().contains(&Expected::Token('+')));
+/// assert!(errors[0].expected().contains(&Expected::EndOfInput));
+///
+/// let errors = parse("99999999999999999999").unwrap_err();
+/// assert_eq!(errors[0].span(), Span::new(0, 20));
+/// assert!(errors[0].expected().is_empty());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    span: Span,
+    expected: Vec<Expected>,
+    found: Option<char>,
+    message: String,
+}
+
+impl ParseError {
+    /// Returns the span of the input where the error occurred.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Returns what the parser expected at the error, empty if the error isn't about an unexpected
+    /// input, e.g., an integer literal out of range.
+    pub fn expected(&self) -> &[Expected] {
+        &self.expected
+    }
+
+    /// Returns the character found at the error, or `None` if the end of input was found or the
+    /// error isn't about an unexpected input.
+    pub fn found(&self) -> Option<char> {
+        self.found
+    }
+
+    /// Returns a human-readable message describing the error.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl ParseError {
+    /// Converts an error of the parser. Not a `From` implementation, to keep the parser library
+    /// out of the public API.
+    pub(crate) fn from_rich(error: Rich<'_, char>) -> Self {
+        let span = Span::new(error.span().start, error.span().end);
+        let message = error.reason().to_string();
+        match error.into_reason() {
+            RichReason::ExpectedFound { expected, found } => Self {
+                span,
+                expected: expected.into_iter().map(Expected::from_pattern).collect(),
+                found: found.as_deref().copied(),
+                message,
+            },
+            RichReason::Custom(_) => Self {
+                span,
+                expected: Vec::new(),
+                found: None,
+                message,
+            },
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.span)
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+/// Something the parser expected to find where a [`ParseError`] occurred.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Expected {
+    /// A specific character.
+    Token(char),
+    /// A named syntactic element, e.g., a keyword.
+    Label(String),
+    /// Any character.
+    Any,
+    /// The end of input.
+    EndOfInput,
+    /// Something other than what was found.
+    SomethingElse,
+}
+
+impl Expected {
+    /// Converts a pattern expected by the parser.
+    fn from_pattern(pattern: RichPattern<'_, char>) -> Self {
+        match pattern {
+            RichPattern::Token(token) => Expected::Token(*token),
+            RichPattern::Label(label) => Expected::Label(label.into_owned()),
+            RichPattern::Identifier(identifier) => Expected::Label(identifier),
+            RichPattern::Any => Expected::Any,
+            RichPattern::SomethingElse => Expected::SomethingElse,
+            RichPattern::EndOfInput => Expected::EndOfInput,
+        }
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Token(token) => write!(f, "'{}'", token.escape_debug()),
+            Expected::Label(label) => write!(f, "{}", label),
+            Expected::Any => write!(f, "any character"),
+            Expected::EndOfInput => write!(f, "end of input"),
+            Expected::SomethingElse => write!(f, "something else"),
+        }
+    }
+}
@@ -0,0 +1,322 @@
+//! Rendering evaluation traces as annotated breakdowns of the rolls.
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+use alloc::{collections::BTreeSet, string::String, vec, vec::Vec};
+#[cfg(feature = "std")]
+use std::collections::BTreeSet;
+
+use core::fmt::Write;
+
+use crate::eval::{DieRoll, TraceNode, Value};
+
+/// The flavor of markup used by [`render_trace`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderStyle {
+    /// Plain text. Discarded dice are put in parentheses, e.g., `4d6kh3 [6, 5, 4, (2)] + 2 = 17`.
+    #[default]
+    Plain,
+    /// Markdown. Discarded dice are struck through, e.g., `4d6kh3 [6, 5, 4, ~~2~~] + 2 = 17`, and
+    /// characters of the expression with a meaning in Markdown are escaped.
+    Markdown,
+    /// Text with ANSI escape codes for terminals. Discarded dice are dimmed and struck through,
+    /// and exploded dice and the result are bold.
+    Ansi,
+}
+
+impl RenderStyle {
+    /// Writes a roll of a die, struck through if it was discarded.
+    fn write_roll(self, out: &mut String, roll: &DieRoll, struck: bool) {
+        let mark = if roll.exploded { "!" } else { "" };
+        let _ = match (self, struck) {
+            (RenderStyle::Plain, true) => write!(out, "({}{})", roll.value, mark),
+            (RenderStyle::Markdown, true) => write!(out, "~~{}{}~~", roll.value, mark),
+            (RenderStyle::Ansi, true) => write!(out, "\x1b[2;9m{}{}\x1b[0m", roll.value, mark),
+            (RenderStyle::Ansi, false) if roll.exploded => {
+                write!(out, "\x1b[1m{}{}\x1b[0m", roll.value, mark)
+            }
+            (_, false) => write!(out, "{}{}", roll.value, mark),
+        };
+    }
+
+    /// Writes a piece of the source text of the expression.
+    fn write_source(self, out: &mut String, source: &str) {
+        match self {
+            RenderStyle::Markdown => {
+                for c in source.chars() {
+                    if matches!(c, '\\' | '*' | '_' | '~' | '`' | '[' | ']' | '<' | '>') {
+                        out.push('\\');
+                    }
+                    out.push(c);
+                }
+            }
+            _ => out.push_str(source),
+        }
+    }
+
+    /// Writes the result of the evaluation.
+    fn write_result(self, out: &mut String, result: i64) {
+        let _ = match self {
+            RenderStyle::Ansi => write!(out, "\x1b[1m{}\x1b[0m", result),
+            _ => write!(out, "{}", result),
+        };
+    }
+}
+
+/// The dice rolled by a node of the trace, to be listed after the source text of the expression
+/// producing them.
+struct Dice<'a> {
+    rolls: &'a [DieRoll],
+    /// The end of the source text after which the dice are listed.
+    end: usize,
+}
+
+/// An element of a list value, which comes from a die.
+struct Element {
+    value: i64,
+    /// The index of the [`Dice`] the element comes from.
+    dice: usize,
+    /// The indices of the rolls making up the element, more than one for compounding explosions.
+    rolls: Vec<usize>,
+}
+
+/// Collects the dice in a trace, and follows the elements they produce through keep and drop
+/// operations, to find the dice discarded.
+struct Collector<'a> {
+    dice: Vec<Dice<'a>>,
+    /// The rolls discarded by keep and drop operations, as pairs of the index of the [`Dice`] and
+    /// the index of the roll.
+    dropped: BTreeSet<(usize, usize)>,
+}
+
+impl<'a> Collector<'a> {
+    /// Collects the dice in the trace of a node, and returns the elements of its value which come
+    /// from dice.
+    fn collect(&mut self, node: &'a TraceNode) -> Vec<Element> {
+        let mut elements: Vec<_> = node
+            .children
+            .iter()
+            .map(|child| self.collect(child))
+            .collect();
+
+        if !node.rolls.is_empty() {
+            return self.collect_dice(node);
+        }
+
+        let Value::List(values) = &node.value else {
+            return Vec::new();
+        };
+
+        // A node passes the elements of its first child through, if it removes nothing but the
+        // elements it drops, e.g., keep and drop operations, and strong lists.
+        let Some((first, passed)) = node.children.first().zip(elements.first_mut()) else {
+            return Vec::new();
+        };
+        let Value::List(first_values) = &first.value else {
+            return Vec::new();
+        };
+        if !is_permutation(first_values, values.iter().chain(&node.dropped)) {
+            return Vec::new();
+        }
+
+        let mut passed = core::mem::take(passed);
+        for element in &passed {
+            let dice = &mut self.dice[element.dice];
+            dice.end = dice.end.max(node.span.end);
+        }
+        for &value in &node.dropped {
+            if let Some(i) = passed.iter().rposition(|element| element.value == value) {
+                let element = passed.remove(i);
+                self.dropped
+                    .extend(element.rolls.iter().map(|&roll| (element.dice, roll)));
+            }
+        }
+        passed
+    }
+
+    /// Records the dice rolled by a dice roll node, and returns the elements of its value.
+    fn collect_dice(&mut self, node: &'a TraceNode) -> Vec<Element> {
+        let dice = self.dice.len();
+        self.dice.push(Dice {
+            rolls: &node.rolls,
+            end: node.span.end,
+        });
+
+        // The dice of a roll counting successes are shown, but the count has no elements.
+        let Value::List(values) = &node.value else {
+            return Vec::new();
+        };
+
+        // Each roll not rerolled is an element, except for extra rolls of compounding
+        // explosions, which are added to the previous element.
+        let kept = node
+            .rolls
+            .iter()
+            .enumerate()
+            .filter(|(_, roll)| !roll.rerolled);
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        if kept.clone().count() == values.len() {
+            groups.extend(kept.map(|(i, _)| vec![i]));
+        } else {
+            for (i, roll) in kept {
+                match groups.last_mut() {
+                    Some(group) if roll.extra => group.push(i),
+                    _ => groups.push(vec![i]),
+                }
+            }
+        }
+        if groups.len() != values.len() {
+            return Vec::new();
+        }
+
+        groups
+            .into_iter()
+            .zip(values)
+            .map(|(rolls, &value)| Element { value, dice, rolls })
+            .collect()
+    }
+}
+
+/// Returns `true` if the elements of `iter` are a permutation of `values`.
+fn is_permutation<'a>(values: &[i64], iter: impl Iterator<Item = &'a i64>) -> bool {
+    let mut values = values.to_vec();
+    let mut other: Vec<i64> = iter.copied().collect();
+    values.sort_unstable();
+    other.sort_unstable();
+    values == other
+}
+
+/// Renders the breakdown of an evaluation, i.e., the source text of the expression with the dice
+/// rolled listed after the parts producing them, followed by the result.
+///
+/// `input` must be the source text the traced expression was parsed from. Dice rerolled and dice
+/// dropped by keep and drop operations are marked as discarded, and exploded dice are marked with
+/// `!`. The result is the sum of the value if it's a list.
+///
+/// # Examples
+///
+/// ```
+/// # use rollkit::{RenderStyle, eval_traced, parse, render_trace};
+/// let input = "4d6kh3 + 2";
+/// let trace = eval_traced(&parse(input).unwrap()).unwrap();
+/// // e.g., "4d6kh3 [6, 5, 4, ~~2~~] + 2 = 17"
+/// println!("{}", render_trace(input, &trace, RenderStyle::Markdown));
+///
+/// let input = "2d1 + 4d1kh3";
+/// let trace = eval_traced(&parse(input).unwrap()).unwrap();
+/// assert_eq!(
+///     render_trace(input, &trace, RenderStyle::Plain),
+///     "2d1 [1, 1] + 4d1kh3 [1, 1, 1, (1)] = 5"
+/// );
+/// ```
+pub fn render_trace(input: &str, trace: &TraceNode, style: RenderStyle) -> String {
+    let mut collector = Collector {
+        dice: Vec::new(),
+        dropped: BTreeSet::new(),
+    };
+    collector.collect(trace);
+
+    let mut order: Vec<usize> = (0..collector.dice.len()).collect();
+    order.sort_by_key(|&dice| collector.dice[dice].end);
+
+    let mut out = String::new();
+    let mut pos = 0;
+    for dice in order {
+        let Dice { rolls, end } = collector.dice[dice];
+        let end = end.clamp(pos, input.len());
+        style.write_source(&mut out, &input[pos..end]);
+        pos = end;
+
+        out.push_str(" [");
+        for (i, roll) in rolls.iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            let struck = roll.rerolled || collector.dropped.contains(&(dice, i));
+            style.write_roll(&mut out, roll, struck);
+        }
+        out.push(']');
+    }
+    style.write_source(&mut out, &input[pos..]);
+
+    out.push_str(" = ");
+    style.write_result(&mut out, trace.value.sum());
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::{EvalContext, eval_traced_with_context, parse};
+
+    fn render(input: &str, style: RenderStyle) -> String {
+        let context = EvalContext {
+            explode_limit: 2,
+            ..Default::default()
+        };
+        let expr = parse(input).unwrap();
+        let trace =
+            eval_traced_with_context(&expr, &mut StdRng::seed_from_u64(0), &context).unwrap();
+        render_trace(input, &trace, style)
+    }
+
+    #[test]
+    fn test_render() {
+        let cases = [
+            ("1 + 2", RenderStyle::Plain, "1 + 2 = 3"),
+            ("3d1", RenderStyle::Plain, "3d1 [1, 1, 1] = 3"),
+            ("3d1 * 2", RenderStyle::Markdown, "3d1 [1, 1, 1] \\* 2 = 6"),
+            ("{3d1}kh2", RenderStyle::Plain, "{3d1}kh2 [1, 1, (1)] = 2"),
+            (
+                "4d1dl1kh2",
+                RenderStyle::Plain,
+                "4d1dl1kh2 [1, 1, (1), (1)] = 2",
+            ),
+            (
+                "{4d1dl1}kh2",
+                RenderStyle::Plain,
+                "{4d1dl1}kh2 [1, 1, (1), (1)] = 2",
+            ),
+            (
+                "2d1! + 1d1",
+                RenderStyle::Plain,
+                "2d1! [1!, 1!, 1, 1!, 1!, 1] + 1d1 [1] = 7",
+            ),
+            (
+                "2d1!!kh1",
+                RenderStyle::Plain,
+                "2d1!!kh1 [1!, 1!, 1, (1!), (1!), (1)] = 3",
+            ),
+            ("1d1!p", RenderStyle::Plain, "1d1!p [1!, 0!, 0] = 1"),
+            ("3d1>=1", RenderStyle::Plain, "3d1>=1 [1, 1, 1] = 3"),
+            (
+                "3d1>=2f1 + 1",
+                RenderStyle::Plain,
+                "3d1>=2f1 [1, 1, 1] + 1 = -2",
+            ),
+            (
+                "1d1 + 1",
+                RenderStyle::Ansi,
+                "1d1 [1] + 1 = \x1b[1m2\x1b[0m",
+            ),
+            (
+                "1d1!dl1",
+                RenderStyle::Ansi,
+                "1d1!dl1 [\x1b[1m1!\x1b[0m, \x1b[1m1!\x1b[0m, \x1b[2;9m1\x1b[0m] = \x1b[1m2\x1b[0m",
+            ),
+        ];
+
+        for (input, style, expected) in cases {
+            assert_eq!(render(input, style), expected, "Input: {}", input);
+        }
+
+        // rerolled dice are struck through
+        let rendered = render("8d2r1", RenderStyle::Markdown);
+        let rolls = rendered.strip_prefix("8d2r1 [").unwrap();
+        let rolls = rolls.strip_suffix("] = 16").unwrap();
+        assert!(rolls.split(", ").all(|roll| roll == "2" || roll == "~~1~~"));
+    }
+}
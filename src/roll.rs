@@ -0,0 +1,198 @@
+//! One-call parsing and evaluation of RollKit expressions.
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+use alloc::{string::String, vec::Vec};
+
+use core::fmt;
+
+use rand::Rng;
+#[cfg(feature = "std")]
+use rand::rng;
+
+use crate::{
+    ast::Span,
+    eval::{EvalContext, EvalError, Value, eval_with_context},
+    parser::{ParseError, parse},
+};
+
+/// An error from [`roll`], [`roll_with`] or [`roll_with_context`], holding the source text of the
+/// expression so it can be rendered with the offending part of the input pointed out.
+///
+/// # Example
+///
+/// ```
+/// # use rollkit::{RollError, roll};
+/// let error = roll("1 + 4d6kh5").unwrap_err();
+/// assert!(matches!(error, RollError::Eval { .. }));
+/// assert_eq!(
+///     error.to_string(),
+///     "Cannot keep 5 elements from a list of 4 elements\n  1 + 4d6kh5\n      ^^^^^^"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RollError {
+    /// The source text failed to parse.
+    Parse {
+        /// The source text.
+        input: String,
+        /// The errors encountered while parsing, at least one.
+        errors: Vec<ParseError>,
+    },
+    /// The parsed expression failed to evaluate.
+    Eval {
+        /// The source text.
+        input: String,
+        /// The error encountered while evaluating.
+        error: EvalError,
+    },
+}
+
+impl RollError {
+    /// Returns the source text of the expression.
+    pub fn input(&self) -> &str {
+        match self {
+            RollError::Parse { input, .. } | RollError::Eval { input, .. } => input,
+        }
+    }
+}
+
+/// Writes the line of `input` containing the start of `span`, with the span underlined by carets.
+fn write_snippet(f: &mut fmt::Formatter<'_>, input: &str, span: Span) -> fmt::Result {
+    let start = span.start.min(input.len());
+    let line_start = input[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = input[start..].find('\n').map_or(input.len(), |i| start + i);
+    let end = span.end.clamp(start, line_end);
+
+    let padding = input[line_start..start].chars().count();
+    let width = input[start..end].chars().count().max(1);
+    write!(
+        f,
+        "\n  {}\n  {:padding$}{:^<width$}",
+        &input[line_start..line_end],
+        "",
+        ""
+    )
+}
+
+impl fmt::Display for RollError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RollError::Parse { input, errors } => {
+                for (i, error) in errors.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", error.message())?;
+                    write_snippet(f, input, error.span())?;
+                }
+                Ok(())
+            }
+            RollError::Eval { input, error } => {
+                write!(f, "{}", error)?;
+                match error.span() {
+                    Some(span) => write_snippet(f, input, span),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl core::error::Error for RollError {}
+
+/// Parses and evaluates a RollKit expression, and returns the result.
+///
+/// The expression is evaluated using the default thread-local random number generator
+/// [`rand::rng`], and therefore requires the standard library. See [`roll_with`] for details and
+/// customization.
+///
+/// # Examples
+///
+/// ```
+/// # use rollkit::roll;
+/// let result = roll("4d6kh3 + 2").unwrap();
+/// assert!((5..=20).contains(&result.sum()));
+///
+/// assert!(roll("4d6kh3 +").is_err());
+/// ```
+#[cfg(feature = "std")]
+pub fn roll(input: &str) -> Result<Value, RollError> {
+    roll_with(input, &mut rng())
+}
+
+/// Parses and evaluates a RollKit expression with a provided random number generator, and returns
+/// the result.
+///
+/// # Examples
+///
+/// ```
+/// # use rand::{SeedableRng, rngs::StdRng};
+/// # use rollkit::roll_with;
+/// let mut rng = StdRng::from_os_rng();
+/// let result = roll_with("4d6kh3 + 2", &mut rng).unwrap();
+/// println!("Result: {}", result);
+/// ```
+pub fn roll_with<R: Rng + ?Sized>(input: &str, rng: &mut R) -> Result<Value, RollError> {
+    roll_with_context(input, rng, &EvalContext::default())
+}
+
+/// Parses and evaluates a RollKit expression with a provided random number generator and
+/// [evaluation context](EvalContext), and returns the result.
+///
+/// # Examples
+///
+/// ```
+/// # use rand::{SeedableRng, rngs::StdRng};
+/// # use rollkit::{EvalContext, EvalLimits, roll_with_context};
+/// let limits = EvalLimits { max_dice: 100, ..Default::default() };
+/// let context = EvalContext { limits, ..Default::default() };
+///
+/// let mut rng = StdRng::from_os_rng();
+/// assert!(roll_with_context("100d6", &mut rng, &context).is_ok());
+/// assert!(roll_with_context("101d6", &mut rng, &context).is_err());
+/// ```
+pub fn roll_with_context<R: Rng + ?Sized>(
+    input: &str,
+    rng: &mut R,
+    context: &EvalContext,
+) -> Result<Value, RollError> {
+    let expr = parse(input).map_err(|errors| RollError::Parse {
+        input: input.into(),
+        errors,
+    })?;
+    eval_with_context(&expr, rng, context).map_err(|error| RollError::Eval {
+        input: input.into(),
+        error,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rand::{SeedableRng, rngs::StdRng};
+
+    #[test]
+    fn test_roll_errors() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut roll = |input| roll_with(input, &mut rng).unwrap_err().to_string();
+
+        assert_eq!(
+            roll("2d6 + (1d0)"),
+            "A die must have at least one side, got 0\n  2d6 + (1d0)\n         ^^^"
+        );
+        assert_eq!(
+            roll("[1, 10, 0]"),
+            "the step of a range list cannot be zero\n  [1, 10, 0]\n          ^"
+        );
+        // the caret points at the end of input
+        assert!(roll("1 +").ends_with("\n  1 +\n     ^"));
+        // only the line containing the error is shown
+        assert_eq!(
+            roll("1 +\n4d6kh5"),
+            "Cannot keep 5 elements from a list of 4 elements\n  4d6kh5\n  ^^^^^^"
+        );
+    }
+}